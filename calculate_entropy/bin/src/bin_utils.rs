@@ -1,2 +1,31 @@
 pub mod get_args;
-pub mod calculate_entropy;
\ No newline at end of file
+pub mod calculate_entropy;
+pub mod reference;
+pub mod annotation;
+pub mod genetic_code;
+pub mod codon;
+pub mod synonymous;
+pub mod covariation;
+pub mod linkage;
+pub mod popgen;
+pub mod consensus;
+pub mod alleles;
+pub mod site_classes;
+pub mod substitutions;
+pub mod metadata;
+pub mod groups;
+pub mod time_bins;
+pub mod compare;
+pub mod rarefaction;
+pub mod output_format;
+pub mod tracks;
+pub mod vcf;
+pub mod plot;
+pub mod logo;
+pub mod report;
+pub mod write_entropies;
+pub mod provenance;
+pub mod convert;
+pub mod config;
+#[cfg(test)]
+pub mod testing;
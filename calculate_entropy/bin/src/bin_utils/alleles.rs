@@ -0,0 +1,111 @@
+/*
+    Allele level table from the column counts
+        Alleles are the bases A, C, G and T in either mode
+            N, ambiguity codes and gaps are not alleles and do not count towards the MAF
+        Per position
+            alleles ranked by count, ties by notation
+            major and minor allele, minor allele frequency (MAF)
+            number of distinct alleles, singletons and doubletons
+        One row per observed allele
+            positions below the minimum MAF are left out
+            alleles below the minimum allele count are left out
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{create_output, notation_counts, output_name};
+use super::get_args::EntropyArgs;
+
+const ALLELES: &str = "ATGC";
+
+pub fn ranked_alleles(char_map: &HashMap<char, f64>) -> Vec<(char, f64)> {
+    // observed bases from the most to the least frequent
+    let mut alleles: Vec<(char, f64)> = ALLELES.chars()
+                                .zip(notation_counts(char_map, ALLELES))
+                                .filter(|(_, count)| *count > 0.0)
+                                .collect();
+    alleles.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    alleles
+}
+
+pub fn report_alleles(map_vec: &[HashMap<char, f64>], file: &FileBufferHelper,
+                      cli: &EntropyArgs) -> Result<(), String> {
+    let out_file_name = output_name(file, &cli.output, "alleles");
+    info!("Allele table output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut allele_writer = BufWriter::new(out_file);
+    writeln!(allele_writer, "Position{delim}Allele{delim}Allele_count{delim}Allele_frequency{delim}\
+                             Major_allele{delim}Minor_allele{delim}MAF{delim}Distinct_alleles{delim}\
+                             Singletons{delim}Doubletons", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    let mut reported_positions = 0usize;
+    map_vec.iter().enumerate().for_each(|(idx, char_map)| {
+        let alleles = ranked_alleles(char_map);
+        let total: f64 = alleles.iter().map(|(_, count)| count).sum();
+        let maf = alleles.get(1).map_or(0.0, |(_, count)| count/total);
+        if alleles.is_empty() || maf < cli.min_maf {
+            return
+        }
+        reported_positions += 1;
+        let major = alleles[0].0;
+        let minor = alleles.get(1).map_or(String::from("NA"), |(x, _)| x.to_string());
+        let singletons = alleles.iter().filter(|(_, count)| *count == 1.0).count();
+        let doubletons = alleles.iter().filter(|(_, count)| *count == 2.0).count();
+        alleles.iter()
+            .filter(|(_, count)| *count >= cli.min_allele_count as f64)
+            .for_each(|(allele, count)| {
+                writeln!(allele_writer, "{pos}{delim}{allele}{delim}{count}{delim}{frequency}{delim}\
+                                         {major}{delim}{minor}{delim}{maf}{delim}{distinct}{delim}\
+                                         {singletons}{delim}{doubletons}",
+                         pos=idx + 1, frequency=count/total, distinct=alleles.len(),
+                         delim=cli.output.delimiter).expect("Unable to write to file");
+            });
+    });
+    info!("{} positions reported in the allele table", reported_positions);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    #[test]
+    fn only_bases_are_alleles() {
+        let (map_vec, _) = counts(&["ANRA", "ANRC", "ANR-", "CNGG", "CAGG"], &Mode::All);
+        assert_eq!(ranked_alleles(&map_vec[0]), [('A', 3.0), ('C', 2.0)]);
+        assert_eq!(ranked_alleles(&map_vec[1]), [('A', 1.0)]);
+        assert_eq!(ranked_alleles(&map_vec[2]), [('G', 2.0)]);
+        // ties are ranked by base
+        assert_eq!(ranked_alleles(&map_vec[3]), [('G', 2.0), ('A', 1.0), ('C', 1.0)]);
+    }
+
+    #[test]
+    fn maf_of_the_bases() {
+        let dir = TestDir::new("alleles");
+        let path = dir.alignment("a.fasta", &[("s1", "AAAA")]);
+        let (map_vec, _) = counts(&["ANRA", "ANRC", "ANR-", "CNGG", "CAGG"], &Mode::All);
+        let cli = entropy_args(&path, &["--alleles"]);
+        let file = check_fasta(&path, true).unwrap();
+        report_alleles(&map_vec, &file, &cli).unwrap();
+        let output = dir.read("a.fasta_shannon_entropy_output_alleles");
+        let rows: Vec<Vec<&str>> = output.lines().skip(1).map(|x| x.split(',').collect()).collect();
+        // Position, Allele, Allele_count, Allele_frequency, Major_allele, Minor_allele, MAF, Distinct_alleles,
+        // Singletons, Doubletons
+        assert_eq!(rows[0], ["1", "A", "3", "0.6", "A", "C", "0.4", "2", "0", "1"]);
+        assert_eq!(rows[1], ["1", "C", "2", "0.4", "A", "C", "0.4", "2", "0", "1"]);
+        // N is the most frequent notation of position 2, A is the only allele
+        assert_eq!(rows[2], ["2", "A", "1", "1", "A", "NA", "0", "1", "1", "0"]);
+        assert_eq!(rows[3][..7], ["3", "G", "2", "1", "G", "NA", "0"]);
+        assert_eq!(rows[4][..7], ["4", "G", "2", "0.5", "G", "A", "0.25"]);
+        assert_eq!(rows.len(), 7);
+        let cli = entropy_args(&path, &["--alleles", "--min-maf", "0.3"]);
+        report_alleles(&map_vec, &file, &cli).unwrap();
+        let output = dir.read("a.fasta_shannon_entropy_output_alleles");
+        assert_eq!(output.lines().skip(1).map(|x| &x[..1]).collect::<Vec<&str>>(), ["1", "1"]);
+    }
+}
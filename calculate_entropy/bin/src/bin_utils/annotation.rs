@@ -0,0 +1,305 @@
+/*
+    Annotate reference positions with features from a GFF3 file
+        Read GFF3 records in reference coordinates
+            skip comments and directives, stop at ##FASTA
+            keep records of the reference sequence only
+                seqid equal to the reference name (header up to the first whitespace)
+                or to one of its "|" separated fields, e.g. MN908947.3 in hCoV-19|MN908947.3|2019-12-26
+        CDS
+            join segments sharing an ID in transcript order
+            drop bases covered by the phase of the first segment
+            assign codon number and codon position (1/2/3) to every base
+        Other features
+            report the feature name without codon context
+            whole sequence features (region, chromosome) are ignored
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use log::{debug, error, info, warn};
+use super::reference::Reference;
+
+const WHOLE_SEQUENCE_FEATURES: [&str; 3] = ["region", "chromosome", "databank_entry"];
+const MISSING_VALUE: &str = "NA";
+const MULTI_VALUE_SEPARATOR: &str = "|";
+
+pub struct FeatureContext {
+    pub name: String,
+    // codon number and codon position (1/2/3) within a coding feature
+    pub codon: Option<(usize, usize)>,
+}
+
+pub struct CodingSequence {
+    pub name: String,
+    pub strand: char,
+    // 1-based reference positions in transcript order, phase removed
+    pub positions: Vec<usize>,
+}
+
+pub struct Annotation {
+    pub coding: Vec<CodingSequence>,
+    // reference position - 1 -> features covering the position
+    pub features: Vec<Vec<FeatureContext>>,
+}
+
+struct GffRecord {
+    seqid: String,
+    feature_type: String,
+    start: usize,
+    end: usize,
+    strand: char,
+    phase: usize,
+    id: Option<String>,
+    name: String,
+}
+
+impl Annotation {
+    pub fn at(&self, position: usize) -> &[FeatureContext] {
+        // features at a 1-based reference position
+        match position {
+            0 => &[],
+            _ => self.features.get(position - 1).map_or(&[], |x| x.as_slice())
+        }
+    }
+
+    pub fn header(delimiter: char) -> String {
+        format!("Feature{delim}Codon_number{delim}Codon_position", delim = delimiter)
+    }
+
+    pub fn fields(&self, position: Option<usize>, delimiter: char) -> String {
+        // Feature, Codon_number and Codon_position columns of an output row
+        match self.values(position) {
+            Some((names, codon_numbers, codon_positions)) =>
+                format!("{names}{delimiter}{codon_numbers}{delimiter}{codon_positions}"),
+            None => format!("{MISSING_VALUE}{delimiter}{MISSING_VALUE}{delimiter}{MISSING_VALUE}")
+        }
+    }
+
+    pub fn values(&self, position: Option<usize>) -> Option<(String, String, String)> {
+        // feature names, codon numbers and codon positions at a position, None without features
+        // overlapping features are joined with "|"
+        let features = position.map_or(&[][..], |x| self.at(x));
+        if features.is_empty() {
+            return None
+        }
+        let join = |f: &dyn Fn(&FeatureContext) -> String| features.iter()
+                                            .map(f)
+                                            .collect::<Vec<String>>()
+                                            .join(MULTI_VALUE_SEPARATOR);
+        let names = join(&|x| x.name.clone());
+        let codon_numbers = join(&|x| x.codon
+                                        .map_or(MISSING_VALUE.to_string(), |(n, _)| n.to_string()));
+        let codon_positions = join(&|x| x.codon
+                                        .map_or(MISSING_VALUE.to_string(), |(_, p)| p.to_string()));
+        Some((names, codon_numbers, codon_positions))
+    }
+}
+
+pub fn read_gff(gff: &PathBuf, reference: &Reference) -> Result<Annotation, String> {
+    let gff_file = match File::open(gff) {
+        Ok(x) => x,
+        Err(x) => {
+            error!("GFF3 file {:?} could not be opened - {}", gff, x);
+            return Err(format!("GFF3 file {:?} could not be opened", gff))
+        }
+    };
+    info!("Reading annotation from {:?}", gff);
+    let reference_length = reference.columns.len();
+    let reference_name = reference.header.split_whitespace().next().unwrap_or("");
+    let mut other_seqids: Vec<String> = Vec::new();
+    let mut records: Vec<GffRecord> = Vec::new();
+    for (line_idx, line) in BufReader::new(gff_file).lines().enumerate() {
+        let line = line.map_err(|x| format!("Unable to read {:?} - {}", gff, x))?;
+        if line.starts_with("##FASTA") {
+            break
+        } else if line.starts_with('#') || line.trim().is_empty() {
+            continue
+        }
+        match parse_gff_line(&line) {
+            Some(record) if record.seqid != reference_name &&
+                            !reference_name.split('|').any(|x| x == record.seqid) => {
+                if !other_seqids.contains(&record.seqid) {
+                    other_seqids.push(record.seqid);
+                }
+            },
+            Some(record) if record.end > reference_length => {
+                warn!("Line {} of {:?} ends at {}, beyond the reference length of {}. Skipped.",
+                    line_idx + 1, gff, record.end, reference_length);
+            },
+            Some(record) => records.push(record),
+            None => warn!("Line {} of {:?} is not a valid GFF3 record. Skipped.", line_idx + 1, gff),
+        }
+    }
+    if !other_seqids.is_empty() {
+        warn!("Records of {:?} in {:?} are not on the reference {}. Skipped.",
+            other_seqids, gff, reference_name);
+    }
+    debug!("{} GFF3 records read", records.len());
+    let coding = build_coding_sequences(&records);
+    info!("{} coding sequences found", coding.len());
+
+    let mut features: Vec<Vec<FeatureContext>> = (0..reference_length).map(|_| Vec::new()).collect();
+    // codon context of coding sequences
+    coding.iter().for_each(|cds| {
+        cds.positions.iter().enumerate().for_each(|(idx, &position)| {
+            let codon = Some((idx / 3 + 1, idx % 3 + 1));
+            let at_position = &mut features[position - 1];
+            // segments of the same CDS may overlap (ribosomal slippage)
+            if !at_position.iter().any(|x| x.name == cds.name && x.codon == codon) {
+                at_position.push(FeatureContext { name: cds.name.clone(), codon });
+            }
+        });
+    });
+    // remaining features are reported by name only,
+    // unless a coding feature of the same name covers the position
+    records.iter()
+        .filter(|x| x.feature_type != "CDS" &&
+            !WHOLE_SEQUENCE_FEATURES.contains(&x.feature_type.as_str()))
+        .for_each(|record| {
+            (record.start..=record.end).for_each(|position| {
+                let at_position = &mut features[position - 1];
+                if !at_position.iter().any(|x| x.name == record.name) {
+                    at_position.push(FeatureContext { name: record.name.clone(), codon: None });
+                }
+            });
+        });
+    Ok(Annotation { coding, features })
+}
+
+fn parse_gff_line(line: &str) -> Option<GffRecord> {
+    // seqid source type start end score strand phase attributes
+    let columns: Vec<&str> = line.split('\t').collect();
+    if columns.len() != 9 {
+        return None
+    }
+    let start: usize = columns[3].parse().ok()?;
+    let end: usize = columns[4].parse().ok()?;
+    if start == 0 || end < start {
+        return None
+    }
+    let attributes: HashMap<&str, &str> = columns[8]
+                                            .split(';')
+                                            .filter_map(|x| x.trim().split_once('='))
+                                            .collect();
+    let id = attributes.get("ID").map(|x| x.to_string());
+    // gene names are more informative than protein IDs for CDS records
+    let name = ["gene", "Name", "ID", "Parent"].iter()
+                    .find_map(|key| attributes.get(key))
+                    .map_or(format!("{}_{}_{}", columns[2], start, end), |x| x.to_string());
+    Some(GffRecord {
+        seqid: columns[0].to_string(),
+        feature_type: columns[2].to_string(),
+        start,
+        end,
+        strand: columns[6].chars().next().unwrap_or('.'),
+        phase: columns[7].parse().unwrap_or(0),
+        id,
+        name,
+    })
+}
+
+fn build_coding_sequences(records: &[GffRecord]) -> Vec<CodingSequence> {
+    // group CDS segments by ID, keeping the order of first appearance
+    let mut order: Vec<String> = Vec::new();
+    let mut segments: HashMap<String, Vec<&GffRecord>> = HashMap::new();
+    records.iter()
+        .filter(|x| x.feature_type == "CDS")
+        .enumerate()
+        .for_each(|(idx, record)| {
+            let key = record.id.clone().unwrap_or(format!("CDS_{idx}"));
+            if !segments.contains_key(&key) {
+                order.push(key.clone());
+            }
+            segments.entry(key).or_default().push(record);
+        });
+    order.iter().map(|key| {
+        let mut cds_segments = segments.remove(key).unwrap();
+        let strand = cds_segments[0].strand;
+        if strand == '-' {
+            cds_segments.sort_by_key(|x| std::cmp::Reverse(x.end));
+        } else {
+            cds_segments.sort_by_key(|x| x.start);
+        }
+        let positions: Vec<usize> = cds_segments.iter()
+                                    .flat_map(|x| -> Box<dyn Iterator<Item = usize>> {
+                                        if strand == '-' {
+                                            Box::new((x.start..=x.end).rev())
+                                        } else {
+                                            Box::new(x.start..=x.end)
+                                        }
+                                    })
+                                    .skip(cds_segments[0].phase)
+                                    .collect();
+        if !positions.len().is_multiple_of(3) {
+            warn!("Coding sequence {} ({}) length {} is not a multiple of 3",
+                cds_segments[0].name, key, positions.len());
+        }
+        CodingSequence {
+            name: cds_segments[0].name.clone(),
+            strand,
+            positions,
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_utils::testing::TestDir;
+
+    fn reference(header: &str, length: usize) -> Reference {
+        Reference {
+            header: header.to_string(),
+            sequence: "A".repeat(length),
+            positions: (1..=length).map(Some).collect(),
+            columns: (0..length).collect(),
+        }
+    }
+
+    fn codon_at(annotation: &Annotation, position: usize) -> Vec<(&str, Option<(usize, usize)>)> {
+        annotation.at(position).iter().map(|x| (x.name.as_str(), x.codon)).collect()
+    }
+
+    #[test]
+    fn two_exon_minus_strand_cds_with_phase() {
+        let dir = TestDir::new("minus_strand_gff");
+        let gff = dir.file("a.gff3", "##gff-version 3\n\
+                                      chr1\t.\tgene\t3\t16\t.\t-\t.\tID=g1;Name=g1\n\
+                                      chr1\t.\tCDS\t3\t7\t.\t-\t2\tID=cds1;Parent=g1;gene=g1\n\
+                                      chr1\t.\tCDS\t12\t16\t.\t-\t1\tID=cds1;Parent=g1;gene=g1\n");
+        let annotation = read_gff(&gff, &reference("chr1 test sequence", 20)).unwrap();
+        assert_eq!(annotation.coding.len(), 1);
+        // transcript order starts at the end of the upstream exon, the phase drops position 16
+        assert_eq!(annotation.coding[0].strand, '-');
+        assert_eq!(annotation.coding[0].positions, [15, 14, 13, 12, 7, 6, 5, 4, 3]);
+        assert_eq!(codon_at(&annotation, 15), [("g1", Some((1, 1)))]);
+        assert_eq!(codon_at(&annotation, 13), [("g1", Some((1, 3)))]);
+        // codon 2 spans the intron
+        assert_eq!(codon_at(&annotation, 12), [("g1", Some((2, 1)))]);
+        assert_eq!(codon_at(&annotation, 7), [("g1", Some((2, 2)))]);
+        assert_eq!(codon_at(&annotation, 3), [("g1", Some((3, 3)))]);
+        // the gene covers the phase and the intron without codon context
+        assert_eq!(codon_at(&annotation, 16), [("g1", None)]);
+        assert_eq!(codon_at(&annotation, 9), [("g1", None)]);
+        assert!(codon_at(&annotation, 17).is_empty());
+        assert_eq!(annotation.fields(Some(7), ','), "g1,2,2");
+        assert_eq!(annotation.fields(Some(20), ','), "NA,NA,NA");
+    }
+
+    #[test]
+    fn records_of_other_seqids_are_skipped() {
+        let dir = TestDir::new("seqid_gff");
+        let gff = dir.file("a.gff3", "NC_045512.2\t.\tCDS\t1\t6\t.\t+\t0\tID=cds1;gene=S\n\
+                                      MN908947.3\t.\tCDS\t4\t9\t.\t+\t0\tID=cds2;gene=N\n");
+        let annotation = read_gff(&gff, &reference("chr1", 12)).unwrap();
+        assert!(annotation.coding.is_empty());
+        assert!((1..=12).all(|x| annotation.at(x).is_empty()));
+        // seqid matching a "|" separated field of the reference name
+        let annotation = read_gff(&gff, &reference("hCoV-19|MN908947.3|2019-12-26 sample", 12)).unwrap();
+        assert_eq!(annotation.coding.len(), 1);
+        assert_eq!(annotation.coding[0].positions, (4..=9).collect::<Vec<usize>>());
+        assert!(annotation.at(1).is_empty());
+    }
+}
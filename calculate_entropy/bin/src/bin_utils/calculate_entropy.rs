@@ -10,23 +10,46 @@
         Calculate the occurrence of all characters
 */
 
-use std::{collections::HashMap, fs::File};
-use std::{io::{BufRead, BufWriter, Write}, sync::{Mutex, Arc}};
-use super::get_args::Mode;
+use std::{collections::{HashMap, HashSet}, fs::{create_dir_all, File}, path::Path};
+use std::{io::{stdout, BufRead, BufWriter, Write}, sync::{mpsc::SyncSender, Mutex, Arc}};
+use super::get_args::{LdFormat, LogoAlphabet, Mode, SubstitutionBase};
 use threadpool::ThreadPool;
 use log::{debug, error, warn, info, trace};
 use check_fasta::lib_utils::struct_helper::FileBufferHelper;
-use crate::bin_utils::get_args::Cli;
+use crate::bin_utils::get_args::{CountingOptions, EntropyArgs, OutputOptions};
+use crate::bin_utils::reference::{load_reference, Reference};
+use crate::bin_utils::annotation::{read_gff, Annotation};
+use crate::bin_utils::codon::report_codon_entropy;
+use crate::bin_utils::synonymous::report_synonymous;
+use crate::bin_utils::covariation::report_mutual_information;
+use crate::bin_utils::linkage::report_linkage;
+use crate::bin_utils::popgen::report_popgen;
+use crate::bin_utils::consensus::report_consensus;
+use crate::bin_utils::alleles::report_alleles;
+use crate::bin_utils::site_classes::{report_site_classes, site_class};
+use crate::bin_utils::substitutions::report_substitutions;
+use crate::bin_utils::groups::report_groups;
+use crate::bin_utils::time_bins::report_time_bins;
+use crate::bin_utils::compare::report_comparison;
+use crate::bin_utils::rarefaction::report_rarefaction;
+use crate::bin_utils::tracks::report_tracks;
+use crate::bin_utils::vcf::report_vcf;
+use crate::bin_utils::plot::report_plot;
+use crate::bin_utils::logo::report_logo;
+use crate::bin_utils::report::report_html;
+use crate::bin_utils::write_entropies::{write_statistics, CountWarning, WarningLog};
+use crate::bin_utils::provenance::write_provenance;
+use crate::bin_utils::output_format::{format_extension, table_format, write_table, ColumnType, Table, Value};
 
 const STANDARD_DNA_NOTATIONS_UPPER: &str = "ATGC";
 const ALL_DNA_NOTATIONS_UPPER: &str = "ATGCUWSMKRYBDHVN";
 const ALL_DNA_NOTATIONS_LOWER: &str = "atgcuwsmkrybdhvn";
 const DNA_ALIGNMENT_NOTATIONS: &str = "-.";
 
-pub fn report_entropy(file: &mut FileBufferHelper, cli: &Cli) {
+pub fn report_entropy(file: &mut FileBufferHelper, cli: &EntropyArgs) -> Result<(), String> {
     // open validated alignment file from main()
     // initialise the HashMap of DNA notatations for every position of the alignment
-    let count_vec: Vec<HashMap<char, f64>> = initialise_structs(file, &cli.mode);
+    let count_vec: Vec<HashMap<char, f64>> = initialise_structs(file, &cli.counting.mode);
     info!("Positions initialised");
     // reset file buffer to zero
     file.buffer_reset();
@@ -40,11 +63,251 @@ pub fn report_entropy(file: &mut FileBufferHelper, cli: &Cli) {
     // read_alignment_file: file buffer mapped to the alignment file
     // suffix: suffix to be added while saving the final output file
     // file: location of the alignment file
-    process_genomes(count_vec, file, cli);
+    // non-permissible characters are logged while counting when requested
+    let log = match cli.log || cli.popgen {
+        true => Some(WarningLog::create(file, &cli.output)?),
+        false => None
+    };
+    let (final_vec, genome_count, warnings) = process_genomes(count_vec, file, cli.counting.nproc,
+                                                              log.as_ref().map(|x| x.sender()));
+    if let Some(log) = log {
+        log.finish();
+    }
+    // run statistics and the provenance of the outputs when requested
+    if cli.statistics || cli.popgen {
+        write_statistics(&final_vec, genome_count, warnings, file, &cli.counting, &cli.output,
+                         &output_name(file, &cli.output, "statistics"))?;
+    }
+    if cli.output.provenance {
+        write_provenance(final_vec.len(), genome_count, file, &cli.output)?;
+    }
+    // map columns to the reference and annotate when requested
+    let reference = if cli.reference.is_some() || cli.gff.is_some() ||
+                        cli.codon_entropy || cli.synonymous ||
+                        (cli.substitutions && cli.substitution_base == SubstitutionBase::Reference) ||
+                        cli.tracks || cli.high_entropy.is_some() || cli.vcf ||
+                        (cli.logo.is_some() && cli.logo_alphabet == LogoAlphabet::Protein) {
+        Some(load_reference(file, cli.reference.as_ref())?)
+    } else {
+        None
+    };
+    let annotation = match (&cli.gff, &reference) {
+        (Some(gff), Some(reference)) => Some(read_gff(gff, reference)?),
+        _ => None
+    };
+    if let (true, Some(reference)) = (cli.codon_entropy, &reference) {
+        report_codon_entropy(file, cli, reference, annotation.as_ref())?;
+    }
+    if let (true, Some(reference)) = (cli.synonymous, &reference) {
+        report_synonymous(&final_vec, file, cli, reference, annotation.as_ref())?;
+    }
+    if cli.mutual_information {
+        report_mutual_information(&final_vec, genome_count, file, cli)?;
+    }
+    if cli.linkage {
+        report_linkage(&final_vec, genome_count, file, cli)?;
+    }
+    if cli.popgen {
+        report_popgen(&final_vec, genome_count, file, cli)?;
+    }
+    if cli.consensus {
+        report_consensus(&final_vec, genome_count, file, &cli.counting, &cli.consensus_options, &cli.output)?;
+    }
+    if cli.alleles {
+        report_alleles(&final_vec, file, cli)?;
+    }
+    if cli.site_classes {
+        report_site_classes(&final_vec, file, cli)?;
+    }
+    if cli.substitutions {
+        report_substitutions(&final_vec, file, cli, reference.as_ref())?;
+    }
+    if cli.group_by.is_some() {
+        report_groups(&final_vec, file, cli)?;
+    }
+    if cli.time_bin.is_some() {
+        report_time_bins(&final_vec, file, cli)?;
+    }
+    if cli.compare.is_some() || cli.compare_groups.is_some() {
+        report_comparison(final_vec.len(), file, cli)?;
+    }
+    if !cli.rarefaction.is_empty() {
+        report_rarefaction(&final_vec, genome_count, file, cli)?;
+    }
+    if let (true, Some(reference)) = (cli.tracks || cli.high_entropy.is_some(), &reference) {
+        report_tracks(&final_vec, genome_count, file, cli, reference)?;
+    }
+    if let (true, Some(reference)) = (cli.vcf, &reference) {
+        report_vcf(&final_vec, genome_count, file, cli, reference)?;
+    }
+    if cli.plot {
+        report_plot(&final_vec, genome_count, file, cli, reference.as_ref(), annotation.as_ref())?;
+    }
+    if cli.logo.is_some() {
+        report_logo(&final_vec, file, cli, reference.as_ref(), annotation.as_ref())?;
+    }
+    if cli.report {
+        report_html(&final_vec, genome_count, warnings, file, cli, reference.as_ref(),
+                    annotation.as_ref())?;
+    }
+    finalise_counts(final_vec, genome_count, cli, file, reference.as_ref(), annotation.as_ref())
+}
+
+// name of an additional output file, next to the main output or in --outdir
+// metric carries the file extension of outputs other than delimited tables, e.g. "logo.svg"
+pub fn output_name(file: &FileBufferHelper, output: &OutputOptions, metric: &str) -> String {
+    metric_output_name(file.path, output, metric)
+}
+
+pub fn metric_output_name(path: &Path, output: &OutputOptions, metric: &str) -> String {
+    let extension = match metric.contains('.') {
+        true => None,
+        false => Some(delimited_extension(output.delimiter))
+    };
+    render_name(path, output, metric, extension)
+}
+
+// name of the main output of a subcommand, "-" when written to stdout
+pub fn main_output_name(path: &Path, output: &OutputOptions, metric: &str) -> String {
+    match output.name_template.as_deref() {
+        Some("-") => String::from("-"),
+        _ => metric_output_name(path, output, metric)
+    }
+}
+
+// name of the entropy table, "-" when written to stdout
+fn entropy_output_name(path: &Path, cli: &EntropyArgs) -> String {
+    let extension = format_extension(cli.output_format);
+    match cli.output.name_template.as_deref() {
+        Some("-") => String::from("-"),
+        Some(_) => render_name(path, &cli.output, "entropy",
+                               Some(extension.unwrap_or(delimited_extension(cli.output.delimiter)))),
+        None => render_name(path, &cli.output, "", None)
+            + &extension.map_or(String::new(), |x| format!(".{x}"))
+    }
+}
+
+// every output of the entropy subcommand for an alignment, following the reports of report_entropy
+pub fn entropy_outputs(path: &Path, cli: &EntropyArgs) -> Vec<String> {
+    let linkage: &[&str] = match cli.ld_format {
+        LdFormat::Long => &["ld"],
+        LdFormat::Matrix => &["ld_r2_matrix", "ld_dprime_matrix"]
+    };
+    let reports: [(bool, &[&str]); 26] = [
+        (cli.log || cli.popgen, &["log.jsonl"]),
+        (cli.statistics || cli.popgen, &["statistics"]),
+        (cli.output.provenance, &["provenance.json"]),
+        (cli.codon_entropy, &["codon"]),
+        (cli.synonymous, &["synonymous", "dnds"]),
+        (cli.mutual_information, &["mutual_information"]),
+        (cli.linkage, linkage),
+        (cli.popgen, &["popgen_statistics"]),
+        (cli.popgen && cli.window_size.is_some(), &["popgen_windows"]),
+        (cli.consensus, &["consensus.fasta"]),
+        (cli.consensus && cli.consensus_options.consensus_support, &["consensus_support"]),
+        (cli.alleles, &["alleles"]),
+        (cli.site_classes, &["site_classes"]),
+        (cli.site_classes && cli.informative_alignment, &["informative.fasta"]),
+        (cli.substitutions, &["substitutions", "mutation_spectrum"]),
+        (cli.group_by.is_some(), &["group_summary", "groups"]),
+        (cli.time_bin.is_some(), &["time_bins", "time_entropy"]),
+        (cli.compare.is_some() || cli.compare_groups.is_some(), &["compare"]),
+        (!cli.rarefaction.is_empty(), &["rarefaction"]),
+        (!cli.rarefaction.is_empty() && cli.window_size.is_some(), &["rarefaction_windows"]),
+        (cli.tracks, &["entropy.bedGraph", "entropy.wig"]),
+        (cli.high_entropy.is_some(), &["high_entropy.bed"]),
+        (cli.vcf, &["variants.vcf"]),
+        (cli.plot, &["entropy_profile.svg"]),
+        (cli.logo.is_some(), &["logo.svg"]),
+        (cli.report, &["report.html"]),
+    ];
+    let mut out_file_names: Vec<String> = reports.iter()
+        .filter(|(requested, _)| *requested)
+        .flat_map(|(_, metrics)| metrics.iter().map(|x| metric_output_name(path, &cli.output, x)))
+        .collect();
+    out_file_names.push(entropy_output_name(path, cli));
+    out_file_names
+}
+
+// <input>_<suffix>_<metric> by default, or the --output template
+// {metric} is the full metric name, {ext} and the "." before it are left out for metrics with their own extension
+fn render_name(path: &Path, output: &OutputOptions, metric: &str, extension: Option<&str>) -> String {
+    let file_name = path.file_name().map_or(String::new(), |x| x.to_string_lossy().to_string());
+    let out_file_name = match output.name_template.as_deref() {
+        Some(template) if template != "-" => {
+            let stem = path.file_stem().map_or(String::new(), |x| x.to_string_lossy().to_string());
+            let template = match extension {
+                Some(extension) => template.replace("{ext}", extension),
+                None => template.replace(".{ext}", "").replace("{ext}", "")
+            };
+            template.replace("{stem}", &stem)
+                    .replace("{name}", &file_name)
+                    .replace("{suffix}", &output.output_suffix)
+                    .replace("{metric}", metric)
+        },
+        _ if metric.is_empty() => format!("{}_{}", file_name, output.output_suffix),
+        _ => format!("{}_{}_{}", file_name, output.output_suffix, metric)
+    };
+    match (&output.outdir, path.parent()) {
+        (Some(outdir), _) => outdir.join(out_file_name).to_string_lossy().to_string(),
+        (None, Some(parent)) => parent.join(out_file_name).to_string_lossy().to_string(),
+        (None, None) => out_file_name
+    }
+}
+
+fn delimited_extension(delimiter: char) -> &'static str {
+    match delimiter {
+        ',' => "csv",
+        '\t' => "tsv",
+        _ => "txt"
+    }
+}
+
+// create an output file, refusing to overwrite an existing one with --no-clobber
+pub fn create_output(out_file_name: &str, output: &OutputOptions) -> Result<File, String> {
+    let path = Path::new(out_file_name);
+    if path.exists() && output.no_clobber {
+        return Err(format!("Unable to create {} - file exists and --no-clobber is given", out_file_name))
+    }
+    if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+        create_dir_all(parent).map_err(|e| format!("Unable to create {} - {}", out_file_name, e))?;
+    }
+    File::create(path).map_err(|e| format!("Unable to create {} - {}", out_file_name, e))
+}
+
+// refuse outputs written more than once, or existing with --no-clobber, before anything is written
+pub fn check_outputs(out_file_names: &[String], output: &OutputOptions) -> Result<(), String> {
+    let mut planned: HashSet<&str> = HashSet::new();
+    let mut problems: Vec<String> = Vec::new();
+    out_file_names.iter().filter(|x| x.as_str() != "-").for_each(|x| {
+        let problem = if !planned.insert(x) {
+            format!("{} would be written more than once, name the outputs apart with --output or --outdir", x)
+        } else if Path::new(x).exists() && output.no_clobber {
+            format!("Unable to create {} - file exists and --no-clobber is given", x)
+        } else {
+            return
+        };
+        if !problems.contains(&problem) {
+            problems.push(problem);
+        }
+    });
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(problems.join("\n"))
+    }
+}
+
+// writer of a main output, stdout for "-"
+pub fn open_output(out_file_name: &str, output: &OutputOptions) -> Result<Box<dyn Write + Send>, String> {
+    if out_file_name == "-" {
+        Ok(Box::new(stdout()))
+    } else {
+        Ok(Box::new(create_output(out_file_name, output)?))
+    }
 }
 
 // Initialise the each position in the alignment
-fn initialise_structs(file: &mut FileBufferHelper, mode: &Mode) -> Vec<HashMap<char, f64>> {
+pub fn initialise_structs(file: &mut FileBufferHelper, mode: &Mode) -> Vec<HashMap<char, f64>> {
     // reading into the first genome
     // estimate length of the alignment
     // initialise Vec of HashMaps
@@ -102,14 +365,17 @@ fn initialise_structs(file: &mut FileBufferHelper, mode: &Mode) -> Vec<HashMap<c
 }
 
 // Tabulate the frequency of each notation at the given position
-fn process_genomes(count_vec: Vec<HashMap<char, f64>>, file: &mut FileBufferHelper,
-                   cli: &Cli) {
+// non-permissible characters are sent to the log when given, and counted
+pub fn process_genomes(count_vec: Vec<HashMap<char, f64>>, file: &mut FileBufferHelper, nproc: usize,
+                       log: Option<&SyncSender<CountWarning>>) -> (Vec<HashMap<char, f64>>, f64, usize) {
     let mut genome = String::new();
     let mut header = String::new();
     let arc_count_vec: Arc<Mutex<Vec<HashMap<char, f64>>>> = Arc::new(
                                                         Mutex::new(
                                                             count_vec));
-    let pool = ThreadPool::new(cli.nproc);
+    // number of non-permissible characters reported by update_counts
+    let arc_warnings: Arc<Mutex<usize>> = Arc::new(Mutex::new(0));
+    let pool = ThreadPool::new(nproc);
     let mut genome_count: f64 = 0.0f64;
     // analyse all genomes
     while file.buffer_reader.read_line(&mut file.line).unwrap_or(0) >= 1 {
@@ -120,10 +386,13 @@ fn process_genomes(count_vec: Vec<HashMap<char, f64>>, file: &mut FileBufferHelp
             } else if !genome.is_empty() {
                 trace!("Processing {}", header);
                 let arc_clone = Arc::clone(&arc_count_vec);
-                let temp_genome = genome.drain(..).collect();
-                let temp_header = header.drain(..).collect();
+                let temp_genome = std::mem::take(&mut genome);
+                let temp_header = std::mem::take(&mut header);
+                let warnings_clone = Arc::clone(&arc_warnings);
+                let log_clone = log.cloned();
                 pool.execute(move || {
-                    analyse_genomes(temp_genome, arc_clone, temp_header);
+                    let warnings = analyse_genomes(temp_genome, arc_clone, temp_header, log_clone);
+                    *warnings_clone.lock().unwrap() += warnings;
                 });
                 header =  file.line.trim().to_string();
             }
@@ -135,74 +404,91 @@ fn process_genomes(count_vec: Vec<HashMap<char, f64>>, file: &mut FileBufferHelp
         }
         file.line.clear();
     }
-    genome_count += 1.0;
+    // every header has been counted, including the last genome
     let arc_clone = Arc::clone(&arc_count_vec);
-    pool.execute(move || {analyse_genomes(genome, arc_clone, header)});
+    let warnings_clone = Arc::clone(&arc_warnings);
+    let log_clone = log.cloned();
+    pool.execute(move || {
+        let warnings = analyse_genomes(genome, arc_clone, header, log_clone);
+        *warnings_clone.lock().unwrap() += warnings;
+    });
     pool.join();
     info!("Threadpool jobs complete");
     let final_vec = Arc::try_unwrap(arc_count_vec).unwrap()
                                                             .into_inner().unwrap();
+    let warnings = Arc::try_unwrap(arc_warnings).unwrap().into_inner().unwrap();
     info!("Arc and mutex unwrapped successfully");
-    finalise_counts(final_vec, genome_count, cli, file)
+    (final_vec, genome_count, warnings)
 }
 // Handle multiple thread requests, call update_counts
 
-fn analyse_genomes(genome: String, arc_clone: Arc<Mutex<Vec<HashMap<char, f64>>>>,
-                                                                        header: String) {
+// returns the number of non-permissible characters of the genome, sent to the log when given
+pub fn analyse_genomes(genome: String, arc_clone: Arc<Mutex<Vec<HashMap<char, f64>>>>,
+                       header: String, log: Option<SyncSender<CountWarning>>) -> usize {
     if let Ok(mut char_map) = arc_clone.lock() {
-        genome.chars().enumerate().for_each( |(idx, x)|
+        genome.chars().enumerate().filter_map( |(idx, x)|
                 update_counts(&mut char_map[idx], &x, &idx, &header)
-        );
+        ).map(|warning| if let Some(sender) = &log {
+            sender.send(warning).expect("Unable to log warning");
+        }).count()
+    } else {
+        0
     }
 }
 
 // update counts at given location
 fn update_counts(column: &mut HashMap<char, f64>, letter: &char, position: &usize,
-                                                                        header: &String) {
+                                                                        header: &String) -> Option<CountWarning> {
     if let Some(val) = column.get_mut(&letter.to_ascii_uppercase()) {
-        *val += 1.0
+        *val += 1.0;
+        None
     } else {
         warn!("Position: {position} in {header} contains non-permissible character: {letter}");
         if let Some(val) = column.get_mut(&'.') {
             *val += 1.0
         }
+        Some(CountWarning {
+            position: position + 1,
+            header: header.trim_start_matches('>').to_string(),
+            character: *letter,
+        })
     }
 }
 
 // arrive at Shannon entropy at each position
 fn finalise_counts(map_vec: Vec<HashMap<char, f64>>, genome_count: f64,
-                   cli: &Cli, file: &mut FileBufferHelper) {
+                   cli: &EntropyArgs, file: &mut FileBufferHelper,
+                   reference: Option<&Reference>, annotation: Option<&Annotation>) -> Result<(), String> {
     // calculate the shannon entropy at every position
     // shannon entropy = sum(-p log_2 p)
-    let atgc: &str = if cli.mode == Mode::Standard {
-        STANDARD_DNA_NOTATIONS_UPPER
-    } else {
-        ALL_DNA_NOTATIONS_UPPER
-    };
-    let genome_count_f64: f64 = f64::from(genome_count);
-    let out_file_name: String = format!("{}_{}",file.path.to_str().unwrap(), cli.output_suffix);
+    let atgc: &str = considered_notations(&cli.counting.mode);
+    let genome_count_f64: f64 = genome_count;
+    let out_file_name: String = entropy_output_name(file.path, cli);
     info!("Output file: {}", out_file_name);
-    let out_file = File::create(out_file_name).expect("Unable to create file");
+    if let Some(format) = table_format(cli.output_format) {
+        // typed columns for the other formats
+        let table = entropy_table(&map_vec, genome_count, cli, reference, annotation);
+        return write_table(&table, &out_file_name, format, &cli.output, cli.precision)
+    }
+    let out_file = open_output(&out_file_name, &cli.output)?;
     let mut entropy_writer = BufWriter::new(out_file);
 
-    // vector to store the counts of characters considered
-    let mut atgc_count_vec: Vec<f64> = Vec::with_capacity(atgc.len());
     info!("Notations considered to calculate Shannon entropy: {}",  atgc);
 
-    // headers of columns that contain the values of fraction of each
-    // character present in a given position
-    let count_headers = atgc.chars()
-                                    .fold(String::new(), |final_str, x|
-                                                        final_str + &cli.delimiter.to_string() +
-                                                            "Count_" + &x.to_string());
+    // reference coordinate and feature context follow the alignment position
+    let reference_headers = match (reference, annotation) {
+        (Some(_), Some(_)) => format!("{delim}Reference_position{delim}{}",
+                                      Annotation::header(cli.output.delimiter), delim=cli.output.delimiter),
+        (Some(_), None) => format!("{}Reference_position", cli.output.delimiter),
+        _ => String::new()
+    };
+    let class_header = if cli.site_classes {
+        format!("{}Site_class", cli.output.delimiter)
+    } else {
+        String::new()
+    };
     // Headers of final output file
-    let headers = format!("Position\
-                        {count_headers}{delim}\
-                        Genome_count{delim}\
-                        Notation_share{delim}\
-                        Fraction_notations{delim}\
-                        Shannon_entropy{delim}\
-                        Validity", delim=cli.delimiter);
+    let headers = format!("Position{reference_headers}{}{class_header}", entropy_headers(cli));
     writeln!(entropy_writer, "{}", headers).expect("Unable to write to file");
 
     // for each position in the alignment, calculate the % of considered characters
@@ -211,37 +497,305 @@ fn finalise_counts(map_vec: Vec<HashMap<char, f64>>, genome_count: f64,
     map_vec.iter().enumerate().for_each( | (idx, char_map) | {
             // idx: position
             // char_map: HashMap of characters considered and their counts
-            atgc_count_vec = atgc.chars()
-                            .map(|n| *char_map.get(&n).unwrap())
-                            .collect();
-            let atgc_share: f64 = atgc_count_vec.iter().sum();
-            let atgc_fraction: f64 = atgc_share/genome_count_f64;
-            let entropy: f64 = get_entropy(&atgc_count_vec);
-            let n_counts = atgc_count_vec.drain(..)
-                                        .map(|x| format!("{}{x}", cli.delimiter))
-                                        .collect::<String>();
-            let validity = if atgc_fraction >= cli.threshold {
-                format!("Valid. Threshold = {}", cli.threshold)
+            let reference_fields = match reference {
+                Some(reference) => {
+                    let position = reference.reference_position(idx);
+                    let annotation_fields = annotation.map_or(String::new(), |x|
+                                    format!("{}{}", cli.output.delimiter, x.fields(position, cli.output.delimiter)));
+                    format!("{}{}{annotation_fields}", cli.output.delimiter,
+                            position.map_or(String::from("NA"), |x| x.to_string()))
+                },
+                None => String::new()
+            };
+            let class_field = if cli.site_classes {
+                format!("{}{}", cli.output.delimiter, site_class(char_map).name())
             } else {
-                format!("Invalid. Threshold = {}", cli.threshold)
+                String::new()
             };
-            writeln!(entropy_writer, "{pos}{counts}{delim}{genome_count}{delim}{atgc_share}{delim}\
-                                        {atgc_fraction}{delim}{shannon}{delim}{validity}",
-                     pos=idx+1, counts=n_counts,shannon=entropy, delim= cli.delimiter).unwrap();
+            writeln!(entropy_writer, "{pos}{reference_fields}{entropy_fields}{class_field}",
+                     pos=idx+1, entropy_fields=entropy_fields(char_map, genome_count_f64, cli)).unwrap();
         }
     );
+    Ok(())
+}
+
+// per position table of typed columns, in the order of the delimited output
+fn entropy_table(map_vec: &[HashMap<char, f64>], genome_count: f64, cli: &EntropyArgs,
+                 reference: Option<&Reference>, annotation: Option<&Annotation>) -> Table {
+    let atgc = considered_notations(&cli.counting.mode);
+    let mut columns: Vec<(String, ColumnType)> = vec![(String::from("Position"), ColumnType::Integer)];
+    if reference.is_some() {
+        columns.push((String::from("Reference_position"), ColumnType::Integer));
+        if annotation.is_some() {
+            ["Feature", "Codon_number", "Codon_position"].iter()
+                .for_each(|x| columns.push((x.to_string(), ColumnType::Text)));
+        }
+    }
+    atgc.chars().for_each(|x| columns.push((format!("Count_{x}"), ColumnType::Integer)));
+    [("Genome_count", ColumnType::Integer), ("Notation_share", ColumnType::Integer),
+     ("Fraction_notations", ColumnType::Float), ("Shannon_entropy", ColumnType::Float),
+     ("Valid", ColumnType::Boolean)].iter()
+        .for_each(|(x, column_type)| columns.push((x.to_string(), *column_type)));
+    if cli.site_classes {
+        columns.push((String::from("Site_class"), ColumnType::Text));
+    }
+    let rows = map_vec.iter().enumerate().map(|(idx, char_map)| {
+        let mut row: Vec<Value> = vec![Value::Integer(Some(idx as u64 + 1))];
+        if let Some(reference) = reference {
+            let position = reference.reference_position(idx);
+            row.push(Value::Integer(position.map(|x| x as u64)));
+            if let Some(annotation) = annotation {
+                let (names, codon_numbers, codon_positions) = match annotation.values(position) {
+                    Some((x, y, z)) => (Some(x), Some(y), Some(z)),
+                    None => (None, None, None)
+                };
+                row.extend([Value::Text(names), Value::Text(codon_numbers), Value::Text(codon_positions)]);
+            }
+        }
+        let atgc_count_vec = notation_counts(char_map, atgc);
+        let atgc_share: f64 = atgc_count_vec.iter().sum();
+        let atgc_fraction: f64 = atgc_share/genome_count;
+        row.extend(atgc_count_vec.iter().map(|x| Value::Integer(Some(*x as u64))));
+        row.extend([Value::Integer(Some(genome_count as u64)),
+                    Value::Integer(Some(atgc_share as u64)),
+                    Value::Float(Some(atgc_fraction)),
+                    Value::Float(Some(get_entropy(&atgc_count_vec))),
+                    Value::Boolean(Some(atgc_fraction >= cli.counting.threshold))]);
+        if cli.site_classes {
+            row.push(Value::Text(Some(site_class(char_map).name().to_string())));
+        }
+        row
+    }).collect();
+    Table { columns, rows }
+}
+
+// headers of the count and entropy columns, each preceded by the delimiter
+pub fn entropy_headers(cli: &EntropyArgs) -> String {
+    // headers of columns that contain the values of fraction of each
+    // character present in a given position
+    let count_headers = considered_notations(&cli.counting.mode).chars()
+                                    .fold(String::new(), |final_str, x|
+                                                        final_str + &cli.output.delimiter.to_string() +
+                                                            "Count_" + &x.to_string());
+    format!("{count_headers}{delim}\
+            Genome_count{delim}\
+            Notation_share{delim}\
+            Fraction_notations{delim}\
+            Shannon_entropy{delim}\
+            Validity", delim=cli.output.delimiter)
 }
 
-fn get_entropy(notation_count: &Vec<f64>) -> f64 {
+// count and entropy columns of a position, each preceded by the delimiter
+pub fn entropy_fields(char_map: &HashMap<char, f64>, genome_count: f64, cli: &EntropyArgs) -> String {
+    let atgc_count_vec = notation_counts(char_map, considered_notations(&cli.counting.mode));
+    let atgc_share: f64 = atgc_count_vec.iter().sum();
+    let atgc_fraction: f64 = atgc_share/genome_count;
+    let entropy: f64 = get_entropy(&atgc_count_vec);
+    let n_counts = atgc_count_vec.iter()
+                                .map(|x| format!("{}{x}", cli.output.delimiter))
+                                .collect::<String>();
+    let validity = if atgc_fraction >= cli.counting.threshold {
+        format!("Valid. Threshold = {}", cli.counting.threshold)
+    } else {
+        format!("Invalid. Threshold = {}", cli.counting.threshold)
+    };
+    format!("{counts}{delim}{genome_count}{delim}{atgc_share}{delim}\
+            {atgc_fraction}{delim}{shannon}{delim}{validity}",
+            counts=n_counts, shannon=entropy, delim=cli.output.delimiter)
+}
+
+// notations considered to calculate Shannon entropy
+pub fn considered_notations(mode: &Mode) -> &'static str {
+    if *mode == Mode::Standard {
+        STANDARD_DNA_NOTATIONS_UPPER
+    } else {
+        ALL_DNA_NOTATIONS_UPPER
+    }
+}
+
+// counts of the considered notations at a position, in the order of notations
+pub fn notation_counts(char_map: &HashMap<char, f64>, notations: &str) -> Vec<f64> {
+    notations.chars()
+        .map(|n| *char_map.get(&n).unwrap())
+        .collect()
+}
+
+// Shannon entropy of positions passing the threshold, None for invalid positions
+pub fn valid_entropies(map_vec: &[HashMap<char, f64>], genome_count: f64,
+                       counting: &CountingOptions) -> Vec<Option<f64>> {
+    let atgc = considered_notations(&counting.mode);
+    map_vec.iter().map(|char_map| {
+        let atgc_count_vec = notation_counts(char_map, atgc);
+        let atgc_share: f64 = atgc_count_vec.iter().sum();
+        if atgc_share/genome_count >= counting.threshold {
+            Some(get_entropy(&atgc_count_vec))
+        } else {
+            None
+        }
+    }).collect()
+}
+
+pub fn get_entropy(notation_count: &[f64]) -> f64 {
+    // no observed state, e.g. an all gapped column, is NaN
+    // a single observed state has no uncertainty
+    match notation_count.iter().filter(|&&count| count > 0.0f64).count() {
+        0 => return f64::NAN,
+        1 => return 0.0f64,
+        _ => {}
+    }
     let sum_considered_places: f64 = notation_count.iter().sum();
     let entropy = notation_count.iter().map(|&count| {
             let p = count/sum_considered_places;
             if p != 0.0f64 {
-                -1.0*p*p.log(2.0)
+                -p*p.log(2.0)
             } else {
                 0.0f64
             }
         }
     ).sum(); // sum (-plogp) where p = N/sum
     entropy
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use clap::Parser;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::{Cli, Command};
+    use crate::bin_utils::testing::{self, counts, TestDir};
+
+    fn output(template: Option<&str>) -> OutputOptions {
+        OutputOptions {
+            output_suffix: String::from("shannon_entropy_output"),
+            delimiter: '\t',
+            outdir: None,
+            name_template: template.map(String::from),
+            provenance: false,
+            no_clobber: false,
+        }
+    }
+
+    #[test]
+    fn template_uses_the_full_metric_name() {
+        let path = PathBuf::from("data/aln.fasta");
+        let output = output(Some("{stem}.{metric}.{ext}"));
+        assert_eq!(render_name(&path, &output, "entropy", Some("tsv")), "data/aln.entropy.tsv");
+        assert_eq!(render_name(&path, &output, "statistics", Some("tsv")), "data/aln.statistics.tsv");
+        assert_eq!(render_name(&path, &output, "entropy.bedGraph", None), "data/aln.entropy.bedGraph");
+        assert_eq!(render_name(&path, &output, "entropy.wig", None), "data/aln.entropy.wig");
+        assert_eq!(render_name(&path, &output, "log.jsonl", None), "data/aln.log.jsonl");
+    }
+
+    #[test]
+    fn default_names_append_the_metric() {
+        let path = PathBuf::from("aln.fasta");
+        let output = output(None);
+        assert_eq!(render_name(&path, &output, "", None), "aln.fasta_shannon_entropy_output");
+        assert_eq!(render_name(&path, &output, "logo.svg", None), "aln.fasta_shannon_entropy_output_logo.svg");
+    }
+
+    fn entropy_args(options: &[&str]) -> EntropyArgs {
+        let args = ["calculate_entropy", "entropy", "-i", "a.fasta", "-m", "standard",
+                    "--outdir", "/nonexistent/outputs"];
+        match Cli::parse_from(args.iter().chain(options)).command {
+            Command::Entropy(x) => *x,
+            _ => unreachable!()
+        }
+    }
+
+    fn planned(inputs: &[&str], cli: &EntropyArgs) -> Vec<String> {
+        inputs.iter().flat_map(|x| entropy_outputs(Path::new(x), cli)).collect()
+    }
+
+    #[test]
+    fn planned_outputs_of_one_alignment_are_apart() {
+        let cli = entropy_args(&["--output", "{stem}.{metric}.{ext}", "--log", "--statistics",
+                                 "--provenance", "--tracks", "--consensus"]);
+        let out_file_names = planned(&["a.fasta"], &cli);
+        ["a.log.jsonl", "a.statistics.csv", "a.provenance.json", "a.consensus.fasta", "a.entropy.bedGraph",
+         "a.entropy.wig", "a.entropy.csv"].iter()
+            .for_each(|x| assert!(out_file_names.contains(&format!("/nonexistent/outputs/{x}")), "{x}"));
+        assert_eq!(out_file_names.len(), 7);
+        assert!(check_outputs(&out_file_names, &cli.output).is_ok());
+    }
+
+    #[test]
+    fn template_without_the_input_collides_across_inputs() {
+        let cli = entropy_args(&["--output", "{metric}.{ext}"]);
+        let error = check_outputs(&planned(&["a.fasta", "b.fasta"], &cli), &cli.output).unwrap_err();
+        assert_eq!(error, "/nonexistent/outputs/entropy.csv would be written more than once, \
+                           name the outputs apart with --output or --outdir");
+        let cli = entropy_args(&["--output", "{stem}.{metric}.{ext}"]);
+        assert!(check_outputs(&planned(&["a.fasta", "b.fasta"], &cli), &cli.output).is_ok());
+    }
+
+    #[test]
+    fn stdout_is_not_a_collision() {
+        let cli = entropy_args(&["--output", "-"]);
+        let out_file_names = planned(&["a.fasta", "b.fasta"], &cli);
+        assert_eq!(out_file_names.iter().filter(|x| *x == "-").count(), 2);
+        assert!(check_outputs(&out_file_names, &cli.output).is_ok());
+    }
+
+    #[test]
+    fn existing_outputs_are_overwritten_unless_no_clobber() {
+        let existing = std::env::temp_dir().join(format!("calculate_entropy_existing_{}", std::process::id()));
+        File::create(&existing).unwrap();
+        let out_file_names = vec![existing.to_string_lossy().to_string()];
+        let cli = entropy_args(&[]);
+        assert!(check_outputs(&out_file_names, &cli.output).is_ok());
+        assert!(create_output(&out_file_names[0], &cli.output).is_ok());
+        let cli = entropy_args(&["--no-clobber"]);
+        let error = check_outputs(&out_file_names, &cli.output).unwrap_err();
+        assert!(error.ends_with("file exists and --no-clobber is given"));
+        assert!(create_output(&out_file_names[0], &cli.output).is_err());
+        std::fs::remove_file(existing).unwrap();
+    }
+
+    fn run_entropy(dir: &TestDir, options: &[&str]) {
+        let path = dir.alignment("a.fasta", &[("s1", "ACGTACGT"), ("s2", "ACGTACGA"), ("s3", "ACGNACGA")]);
+        let cli = testing::entropy_args(&path, options);
+        let mut file = check_fasta(&path, true).unwrap();
+        report_entropy(&mut file, &cli).unwrap();
+    }
+
+    #[test]
+    fn default_run_writes_only_the_main_output() {
+        let dir = TestDir::new("default_run");
+        run_entropy(&dir, &[]);
+        assert_eq!(dir.files(), ["a.fasta", "a.fasta_shannon_entropy_output"]);
+        let dir = TestDir::new("requested_reports");
+        run_entropy(&dir, &["--log", "--statistics", "--provenance"]);
+        assert_eq!(dir.files(), ["a.fasta", "a.fasta_shannon_entropy_output",
+                                 "a.fasta_shannon_entropy_output_log.jsonl",
+                                 "a.fasta_shannon_entropy_output_provenance.json",
+                                 "a.fasta_shannon_entropy_output_statistics"]);
+        assert!(dir.read("a.fasta_shannon_entropy_output_log.jsonl").contains("\"character\":\"N\""));
+    }
+
+    #[test]
+    fn genome_count_is_the_number_of_sequences() {
+        let (_, genome_count) = counts(&["ACGT", "ACGA", "AC-A"], &Mode::Standard);
+        assert_eq!(genome_count, 3.0);
+        let dir = TestDir::new("genome_count");
+        run_entropy(&dir, &[]);
+        let output = dir.read("a.fasta_shannon_entropy_output");
+        let mut rows = output.lines().map(|x| x.split(',').collect::<Vec<&str>>());
+        let header = rows.next().unwrap();
+        let genome_count = header.iter().position(|x| *x == "Genome_count").unwrap();
+        let fraction = header.iter().position(|x| *x == "Fraction_notations").unwrap();
+        rows.for_each(|row| assert_eq!(row[genome_count], "3"));
+        // the N of the third sequence is not a considered notation
+        let row = output.lines().nth(4).unwrap().split(',').collect::<Vec<&str>>();
+        assert_eq!(row[fraction].parse::<f64>().unwrap(), 2.0/3.0);
+    }
+
+    #[test]
+    fn entropy_of_observed_states() {
+        assert!(get_entropy(&[0.0, 0.0, 0.0, 0.0]).is_nan());
+        assert_eq!(get_entropy(&[3.0, 0.0, 0.0, 0.0]), 0.0);
+        assert_eq!(get_entropy(&[2.0, 2.0, 0.0, 0.0]), 1.0);
+        assert_eq!(get_entropy(&[1.0, 1.0, 1.0, 1.0]), 2.0);
+    }
+}
@@ -0,0 +1,235 @@
+/*
+    Codon and amino acid entropy
+        Codons are taken from the CDS features of the annotation
+            or from a reading frame over the whole reference
+        For every sequence and codon
+            read the alignment columns of the three reference bases
+            complement bases of minus strand features
+            gap in any base: gapped codon, not counted
+            ambiguity code in any base: ambiguous codon
+                translated only if all resolutions give the same amino acid
+            otherwise: count codon and amino acid
+        Shannon entropy over the observed codons and the translated amino acids
+            NA without observed codons or amino acids, e.g. a codon gapped in every sequence
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+use log::{debug, info, trace};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::annotation::{Annotation, CodingSequence};
+use super::calculate_entropy::{create_output, get_entropy, output_name};
+use super::genetic_code::{complement, expand_base, GeneticCode};
+use super::get_args::EntropyArgs;
+use super::reference::Reference;
+
+const GAP_NOTATIONS: &str = "-.";
+
+pub struct CodonSite {
+    pub feature: String,
+    pub codon_number: usize,
+    pub minus_strand: bool,
+    // reference positions in transcript order
+    pub positions: [usize; 3],
+    // alignment columns in transcript order
+    pub columns: [usize; 3],
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CodonCounts {
+    pub codons: HashMap<String, f64>,
+    pub amino_acids: HashMap<char, f64>,
+    pub gapped: f64,
+    pub ambiguous: f64,
+}
+
+impl CodonSite {
+    pub fn codon(&self, genome: &[u8]) -> Option<[char; 3]> {
+        // bases of the codon in a sequence, in transcript orientation
+        let mut codon = ['N'; 3];
+        for (idx, column) in self.columns.iter().enumerate() {
+            let base = (*genome.get(*column)? as char).to_ascii_uppercase();
+            codon[idx] = if self.minus_strand { complement(base) } else { base };
+        }
+        Some(codon)
+    }
+}
+
+pub fn report_codon_entropy(file: &mut FileBufferHelper, cli: &EntropyArgs, reference: &Reference,
+                            annotation: Option<&Annotation>) -> Result<(), String> {
+    let genetic_code = GeneticCode::new(cli.genetic_code)
+                                        .expect("Translation table validated by the parser");
+    let sites = Arc::new(codon_sites(reference, annotation, cli.frame as usize));
+    info!("{} codons considered for codon entropy", sites.len());
+    let codon_counts = count_codons(file, cli, Arc::clone(&sites), genetic_code);
+    write_codon_entropy(&sites, &codon_counts, file, cli)
+}
+
+pub fn codon_sites(reference: &Reference, annotation: Option<&Annotation>,
+                   frame: usize) -> Vec<CodonSite> {
+    // codons from CDS features, or from the reading frame over the reference
+    let frame_cds;
+    let coding: &[CodingSequence] = match annotation {
+        Some(x) => &x.coding,
+        None => {
+            frame_cds = [CodingSequence {
+                name: format!("Frame_{}", frame),
+                strand: '+',
+                positions: (frame..=reference.columns.len()).collect(),
+            }];
+            &frame_cds
+        }
+    };
+    coding.iter().flat_map(|cds| {
+        cds.positions.chunks_exact(3).enumerate().map(|(idx, positions)| {
+            let positions = [positions[0], positions[1], positions[2]];
+            CodonSite {
+                feature: cds.name.clone(),
+                codon_number: idx + 1,
+                minus_strand: cds.strand == '-',
+                positions,
+                columns: positions.map(|x| reference.columns[x - 1]),
+            }
+        }).collect::<Vec<CodonSite>>()
+    }).collect()
+}
+
+pub fn count_codons(file: &mut FileBufferHelper, cli: &EntropyArgs, arc_sites: Arc<Vec<CodonSite>>,
+                    genetic_code: GeneticCode) -> Vec<CodonCounts> {
+    let arc_counts = Arc::new(Mutex::new(vec![CodonCounts::default(); arc_sites.len()]));
+    let arc_code = Arc::new(genetic_code);
+    let pool = ThreadPool::new(cli.counting.nproc);
+    file.buffer_reset();
+    while let Some((header, genome)) = file.next_record() {
+        trace!("Counting codons of {}", header);
+        let counts_clone = Arc::clone(&arc_counts);
+        let sites_clone = Arc::clone(&arc_sites);
+        let code_clone = Arc::clone(&arc_code);
+        pool.execute(move || {
+            if let Ok(mut codon_counts) = counts_clone.lock() {
+                sites_clone.iter().zip(codon_counts.iter_mut()).for_each(|(site, counts)|
+                    update_codon_counts(counts, site.codon(genome.as_bytes()), &code_clone)
+                );
+            }
+        });
+    }
+    pool.join();
+    file.buffer_reset();
+    debug!("Codon counting complete");
+    Arc::try_unwrap(arc_counts).unwrap().into_inner().unwrap()
+}
+
+fn update_codon_counts(counts: &mut CodonCounts, codon: Option<[char; 3]>,
+                       genetic_code: &GeneticCode) {
+    let codon = match codon {
+        Some(x) => x,
+        None => {
+            // sequence shorter than the alignment
+            counts.gapped += 1.0;
+            return
+        }
+    };
+    if codon.iter().any(|x| GAP_NOTATIONS.contains(*x)) {
+        counts.gapped += 1.0;
+    } else if let Some(amino_acid) = genetic_code.translate(&codon) {
+        *counts.codons.entry(codon.iter().collect()).or_insert(0.0) += 1.0;
+        *counts.amino_acids.entry(amino_acid).or_insert(0.0) += 1.0;
+    } else {
+        counts.ambiguous += 1.0;
+        if codon.iter().all(|x| expand_base(*x).is_some()) {
+            if let Some(amino_acid) = genetic_code.translate_ambiguous(&codon) {
+                *counts.amino_acids.entry(amino_acid).or_insert(0.0) += 1.0;
+            }
+        }
+    }
+}
+
+fn major<T: Ord + Clone + ToString>(counts: &HashMap<T, f64>) -> String {
+    // most frequent symbol, ties resolved by sort order
+    let mut symbols: Vec<(&T, &f64)> = counts.iter().collect();
+    symbols.sort_by(|a, b| b.1.total_cmp(a.1).then(a.0.cmp(b.0)));
+    symbols.first().map_or(String::from("NA"), |(x, _)| x.to_string())
+}
+
+// number of observed symbols, an empty sum of f64 is -0
+fn total(values: &[f64]) -> f64 {
+    values.iter().fold(0.0, |sum, x| sum + x)
+}
+
+// Shannon entropy of the observed symbols, NA without any
+fn entropy(values: &[f64]) -> String {
+    match values.is_empty() {
+        true => String::from("NA"),
+        false => get_entropy(values).to_string()
+    }
+}
+
+fn write_codon_entropy(sites: &[CodonSite], codon_counts: &[CodonCounts],
+                       file: &FileBufferHelper, cli: &EntropyArgs) -> Result<(), String> {
+    let out_file_name = output_name(file, &cli.output, "codon");
+    info!("Codon entropy output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut codon_writer = BufWriter::new(out_file);
+    writeln!(codon_writer, "Feature{delim}Codon_number{delim}Reference_start{delim}\
+                            Valid_codons{delim}Gapped_codons{delim}Ambiguous_codons{delim}\
+                            Distinct_codons{delim}Codon_entropy{delim}Major_codon{delim}\
+                            Translated_codons{delim}Distinct_amino_acids{delim}\
+                            Amino_acid_entropy{delim}Major_amino_acid", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    sites.iter().zip(codon_counts).for_each(|(site, counts)| {
+        let codon_values: Vec<f64> = counts.codons.values().copied().collect();
+        let amino_acid_values: Vec<f64> = counts.amino_acids.values().copied().collect();
+        writeln!(codon_writer, "{feature}{delim}{number}{delim}{start}{delim}\
+                                {valid}{delim}{gapped}{delim}{ambiguous}{delim}\
+                                {distinct_codons}{delim}{codon_entropy}{delim}{major_codon}{delim}\
+                                {translated}{delim}{distinct_amino_acids}{delim}\
+                                {amino_acid_entropy}{delim}{major_amino_acid}",
+                 feature=site.feature, number=site.codon_number,
+                 start=site.positions.iter().min().unwrap(),
+                 valid=total(&codon_values), gapped=counts.gapped,
+                 ambiguous=counts.ambiguous, distinct_codons=codon_values.len(),
+                 codon_entropy=entropy(&codon_values), major_codon=major(&counts.codons),
+                 translated=total(&amino_acid_values),
+                 distinct_amino_acids=amino_acid_values.len(),
+                 amino_acid_entropy=entropy(&amino_acid_values),
+                 major_amino_acid=major(&counts.amino_acids), delim=cli.output.delimiter)
+            .expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::reference::load_reference;
+    use crate::bin_utils::testing::{entropy_args, TestDir};
+
+    #[test]
+    fn codon_entropy_of_a_reading_frame() {
+        let dir = TestDir::new("codon_entropy");
+        let path = dir.alignment("a.fasta", &[("s1", "ATGAAATTTNNN"),
+                                              ("s2", "ATGAAGTTCNNN"),
+                                              ("s3", "ATG---TTANNN")]);
+        let cli = entropy_args(&path, &["--codon-entropy"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        let reference = load_reference(&mut file, None).unwrap();
+        report_codon_entropy(&mut file, &cli, &reference, None).unwrap();
+        let output = dir.read("a.fasta_shannon_entropy_output_codon");
+        let rows: Vec<Vec<&str>> = output.lines().skip(1).map(|x| x.split(',').collect()).collect();
+        // Feature, Codon_number, Reference_start, Valid, Gapped, Ambiguous, Distinct_codons, Codon_entropy,
+        // Major_codon, Translated, Distinct_amino_acids, Amino_acid_entropy, Major_amino_acid
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], ["Frame_1", "1", "1", "3", "0", "0", "1", "0", "ATG", "3", "1", "0", "M"]);
+        assert_eq!(rows[1], ["Frame_1", "2", "4", "2", "1", "0", "2", "1", "AAA", "2", "1", "0", "K"]);
+        assert_eq!(rows[2][..7], ["Frame_1", "3", "7", "3", "0", "0", "3"]);
+        assert!((rows[2][7].parse::<f64>().unwrap() - 3.0f64.log2()).abs() < 1e-12);
+        assert_eq!(rows[2][12], "F");
+        let amino_acid_entropy = -(2.0/3.0)*(2.0f64/3.0).log2() - (1.0/3.0)*(1.0f64/3.0).log2();
+        assert!((rows[2][11].parse::<f64>().unwrap() - amino_acid_entropy).abs() < 1e-12);
+        // ambiguous in every sequence: no codon observed
+        assert_eq!(rows[3], ["Frame_1", "4", "10", "0", "0", "3", "0", "NA", "NA", "0", "0", "NA", "NA"]);
+    }
+}
@@ -0,0 +1,289 @@
+/*
+    Differential entropy between two sets of sequences
+        First set: the input alignment, or the first metadata group
+        Second set: the alignment given to --compare, or the second metadata group
+            alignments must be of the same length
+        Per position
+            Shannon entropy of both sets over the considered notations
+            difference = second - first
+            permutation test
+                shuffle the set labels of the sequences, keeping the set sizes
+                p = (1 + permutations with |difference| >= observed)/(1 + permutations)
+                positions constant over both sets are not permuted, p = 1
+            Benjamini-Hochberg q-value over all positions with a p-value
+        Every position is permuted with its own generator, seeded from the seed and the position
+    Both sets are held in memory, one byte per notation: memory grows as sequences x alignment length
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use threadpool::ThreadPool;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use log::info;
+use check_fasta::check_fasta;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{considered_notations, create_output, get_entropy, output_name};
+use super::covariation::MISSING_NOTATION;
+use super::get_args::EntropyArgs;
+use super::metadata::{read_metadata, sequence_id};
+
+struct Comparison {
+    position: usize,
+    first: Option<f64>,
+    second: Option<f64>,
+    p_value: Option<f64>,
+}
+
+pub fn report_comparison(alignment_length: usize, file: &mut FileBufferHelper,
+                         cli: &EntropyArgs) -> Result<(), String> {
+    let notations = considered_notations(&cli.counting.mode);
+    let lookup: HashMap<char, u8> = notations.chars()
+                                        .enumerate()
+                                        .map(|(idx, x)| (x, idx as u8))
+                                        .collect();
+    let mut columns: Vec<Vec<u8>> = vec![Vec::new(); alignment_length];
+    // true for sequences of the second set
+    let mut labels: Vec<bool> = Vec::new();
+    let collected = match (&cli.compare, &cli.compare_groups) {
+        (Some(second_file), _) => append_columns(file, &lookup, &mut columns, &mut labels, |_| Some(false))
+            .and_then(|_| append_alignment(second_file, &lookup, &mut columns, &mut labels)),
+        (None, Some(groups)) => compared_groups(file, groups, cli, &lookup, &mut columns, &mut labels),
+        _ => return Ok(())
+    };
+    collected?;
+    let second_count = labels.iter().filter(|x| **x).count();
+    info!("Comparing {} sequences against {}", labels.len() - second_count, second_count);
+    if second_count == 0 || second_count == labels.len() {
+        return Err(format!("Both sets need at least one sequence to compare {:?}", file.path))
+    }
+
+    let seed = cli.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    info!("Permutation seed: {}", seed);
+    let comparisons = permutation_tests(Arc::new(columns), Arc::new(labels), notations.len(), seed, cli);
+    let q_values = benjamini_hochberg(&comparisons.iter().map(|x| x.p_value).collect::<Vec<Option<f64>>>());
+
+    let out_file_name = output_name(file, &cli.output, "compare");
+    info!("Differential entropy output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut compare_writer = BufWriter::new(out_file);
+    writeln!(compare_writer, "Position{delim}Entropy_first{delim}Entropy_second{delim}\
+                              Entropy_difference{delim}P_value{delim}Q_value", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    let na = |x: Option<f64>| x.map_or(String::from("NA"), |x| x.to_string());
+    comparisons.iter().zip(q_values).for_each(|(x, q_value)| {
+        let difference = match (x.first, x.second) {
+            (Some(first), Some(second)) => Some(second - first),
+            _ => None
+        };
+        writeln!(compare_writer, "{pos}{delim}{first}{delim}{second}{delim}{difference}{delim}\
+                                  {p_value}{delim}{q_value}",
+                 pos=x.position + 1, first=na(x.first), second=na(x.second), difference=na(difference),
+                 p_value=na(x.p_value), q_value=na(q_value), delim=cli.output.delimiter)
+            .expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+// notation index of every sequence at every column, with the set of the sequence
+// label_of: set of a sequence from its header, None to leave the sequence out
+fn append_columns<F>(file: &mut FileBufferHelper, lookup: &HashMap<char, u8>, columns: &mut [Vec<u8>],
+                     labels: &mut Vec<bool>, label_of: F) -> Result<(), String>
+where F: Fn(&str) -> Option<bool> {
+    file.buffer_reset();
+    while let Some((header, genome)) = file.next_record() {
+        let label = match label_of(&header) {
+            Some(x) => x,
+            None => continue
+        };
+        if genome.len() != columns.len() {
+            file.buffer_reset();
+            return Err(format!("{} in {:?} is {} long, the alignment compared is {} long",
+                               header, file.path, genome.len(), columns.len()))
+        }
+        genome.chars().zip(columns.iter_mut()).for_each(|(x, column)| {
+            column.push(lookup.get(&x.to_ascii_uppercase()).copied().unwrap_or(MISSING_NOTATION));
+        });
+        labels.push(label);
+    }
+    file.buffer_reset();
+    Ok(())
+}
+
+fn append_alignment(path: &PathBuf, lookup: &HashMap<char, u8>, columns: &mut [Vec<u8>],
+                    labels: &mut Vec<bool>) -> Result<(), String> {
+    let mut second_file = check_fasta(path, true).map_err(|e| e.to_string())?;
+    info!("Alignment to compare complies requirements {:?}", path);
+    append_columns(&mut second_file, lookup, columns, labels, |_| Some(true))
+}
+
+fn compared_groups(file: &mut FileBufferHelper, groups: &[String], cli: &EntropyArgs, lookup: &HashMap<char, u8>,
+                   columns: &mut [Vec<u8>], labels: &mut Vec<bool>) -> Result<(), String> {
+    let (metadata_path, group_column) = match (&cli.metadata, &cli.group_by) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return Err(String::from("Comparing groups needs --metadata and --group-by"))
+    };
+    let metadata = read_metadata(metadata_path, cli.metadata_id.as_ref())?;
+    let group_index = metadata.column_index(group_column)
+        .ok_or(format!("Group column {} not found in metadata {:?}", group_column, metadata_path))?;
+    info!("Comparing group {} against {} of {}", groups[0], groups[1], group_column);
+    append_columns(file, lookup, columns, labels, |header| {
+        match sequence_id(header, cli).and_then(|id| metadata.value(id, group_index)) {
+            Some(x) if x == groups[0] => Some(false),
+            Some(x) if x == groups[1] => Some(true),
+            _ => None
+        }
+    })
+}
+
+fn permutation_tests(columns: Arc<Vec<Vec<u8>>>, labels: Arc<Vec<bool>>, n_notations: usize,
+                     seed: u64, cli: &EntropyArgs) -> Vec<Comparison> {
+    // one job per position
+    let pool = ThreadPool::new(cli.counting.nproc);
+    let (sender, receiver) = mpsc::channel();
+    let permutations = cli.permutations;
+    for position in 0..columns.len() {
+        let columns_clone = Arc::clone(&columns);
+        let labels_clone = Arc::clone(&labels);
+        let sender_clone = sender.clone();
+        pool.execute(move || {
+            let column = &columns_clone[position];
+            let (first, second) = set_entropies(column, &labels_clone, n_notations);
+            let p_value = match (first, second) {
+                (Some(first), Some(second)) => {
+                    let observed = (second - first).abs();
+                    let pooled = column.iter().filter(|x| **x != MISSING_NOTATION).collect::<Vec<&u8>>();
+                    if pooled.windows(2).all(|x| x[0] == x[1]) {
+                        Some(1.0)
+                    } else {
+                        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(position as u64));
+                        let mut permuted = labels_clone.to_vec();
+                        let extreme = (0..permutations).filter(|_| {
+                            permuted.shuffle(&mut rng);
+                            match set_entropies(column, &permuted, n_notations) {
+                                (Some(first), Some(second)) => (second - first).abs() >= observed - 1e-12,
+                                _ => false
+                            }
+                        }).count();
+                        Some((1 + extreme) as f64/(1 + permutations) as f64)
+                    }
+                },
+                _ => None
+            };
+            sender_clone.send(Comparison { position, first, second, p_value })
+                .expect("Unable to send comparison");
+        });
+    }
+    drop(sender);
+    let mut comparisons: Vec<Comparison> = receiver.iter().collect();
+    comparisons.sort_by_key(|x| x.position);
+    comparisons
+}
+
+// entropy of the first and second set, None for a set without considered notations
+fn set_entropies(column: &[u8], labels: &[bool], n_notations: usize) -> (Option<f64>, Option<f64>) {
+    let mut first = vec![0.0f64; n_notations];
+    let mut second = vec![0.0f64; n_notations];
+    column.iter().zip(labels).filter(|(x, _)| **x != MISSING_NOTATION).for_each(|(x, label)| {
+        if *label {
+            second[*x as usize] += 1.0;
+        } else {
+            first[*x as usize] += 1.0;
+        }
+    });
+    let entropy = |counts: &[f64]| if counts.iter().sum::<f64>() > 0.0 {
+        Some(get_entropy(counts))
+    } else {
+        None
+    };
+    (entropy(&first), entropy(&second))
+}
+
+// Benjamini-Hochberg adjusted p-values, None stays None
+pub fn benjamini_hochberg(p_values: &[Option<f64>]) -> Vec<Option<f64>> {
+    let mut ranked: Vec<(usize, f64)> = p_values.iter().enumerate()
+                                        .filter_map(|(idx, x)| x.map(|p| (idx, p)))
+                                        .collect();
+    ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let tests = ranked.len() as f64;
+    let mut q_values: Vec<Option<f64>> = vec![None; p_values.len()];
+    let mut running_min = 1.0f64;
+    ranked.iter().enumerate().rev().for_each(|(rank, (idx, p))| {
+        running_min = running_min.min(p * tests/(rank + 1) as f64);
+        q_values[*idx] = Some(running_min);
+    });
+    q_values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_utils::testing::{entropy_args, TestDir};
+
+    const FIRST: [(&str, &str); 6] = [("a1", "AAAC"), ("a2", "AAAC"), ("a3", "AATC"), ("a4", "ACTC"),
+                                      ("a5", "ACGC"), ("a6", "AAGC")];
+    const SECOND: [(&str, &str); 6] = [("b1", "ACTC"), ("b2", "AGTC"), ("b3", "ATGC"), ("b4", "AGAC"),
+                                       ("b5", "ACCC"), ("b6", "A-CC")];
+
+    // p-values of the comparison of the two sets
+    fn compared_p_values(dir: &TestDir, seed: &str) -> Vec<String> {
+        let path = dir.alignment("first.fasta", &FIRST);
+        let second = dir.alignment("second.fasta", &SECOND);
+        let cli = entropy_args(&path, &["--compare", second.to_str().unwrap(), "--permutations", "50",
+                                        "--seed", seed]);
+        let mut file = check_fasta(&path, true).unwrap();
+        report_comparison(4, &mut file, &cli).unwrap();
+        dir.read("first.fasta_shannon_entropy_output_compare").lines().skip(1)
+            .map(|x| x.split(',').nth(4).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn permutations_are_seeded() {
+        let dir = TestDir::new("compare_seed");
+        let p_values = compared_p_values(&dir, "7");
+        // constant positions are not permuted
+        assert_eq!(p_values[0], "1");
+        assert_eq!(p_values[3], "1");
+        assert_eq!(compared_p_values(&dir, "7"), p_values);
+        assert_ne!(compared_p_values(&dir, "8"), p_values);
+    }
+
+    #[test]
+    fn alignments_of_different_lengths_are_rejected() {
+        let dir = TestDir::new("compare_length");
+        let path = dir.alignment("first.fasta", &FIRST);
+        let second = dir.alignment("second.fasta", &[("b1", "ACTCA"), ("b2", "AGTCA")]);
+        let cli = entropy_args(&path, &["--compare", second.to_str().unwrap()]);
+        let mut file = check_fasta(&path, true).unwrap();
+        let error = report_comparison(4, &mut file, &cli).unwrap_err();
+        assert!(error.contains("is 5 long, the alignment compared is 4 long"), "{error}");
+    }
+
+    fn assert_q_values(p_values: &[Option<f64>], expected: &[Option<f64>]) {
+        let q_values = benjamini_hochberg(p_values);
+        assert_eq!(q_values.len(), expected.len());
+        q_values.iter().zip(expected).for_each(|(q, expected)| match (q, expected) {
+            (Some(q), Some(expected)) => assert!((q - expected).abs() < 1e-12, "{q} {expected}"),
+            (q, expected) => assert_eq!(q, expected)
+        });
+    }
+
+    #[test]
+    fn benjamini_hochberg_fixed_p_values() {
+        // p.adjust(c(0.01, 0.04, 0.03, 0.005, 0.5), "BH") in R
+        assert_q_values(&[Some(0.01), Some(0.04), Some(0.03), Some(0.005), None, Some(0.5)],
+                        &[Some(0.025), Some(0.05), Some(0.05), Some(0.025), None, Some(0.5)]);
+    }
+
+    #[test]
+    fn benjamini_hochberg_keeps_the_order_of_p_values() {
+        // 0.8 * 2/1 is above the q-value of the larger p-value
+        assert_q_values(&[Some(0.9), Some(0.8)], &[Some(0.9), Some(0.9)]);
+        assert_q_values(&[Some(1.0)], &[Some(1.0)]);
+        assert_q_values(&[None, None], &[None, None]);
+    }
+}
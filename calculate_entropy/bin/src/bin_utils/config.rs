@@ -0,0 +1,335 @@
+/*
+    Run configuration files (--config) in TOML, or YAML for .yaml/.yml files
+        keys are the long option names of the subcommand, with "-" or "_"
+            e.g. mode = "standard", threshold = 0.8, infile = ["a.fasta", "b.fasta"], codon-entropy = true
+        top level keys apply to the subcommand that is run
+        a table named after a subcommand, e.g. [entropy], overrides the top level keys for that subcommand
+            tables of the other subcommands are ignored
+    Options given on the command line override the configuration
+        the configuration is turned into command line options for the options not given, then parsed again
+    --print-config prints the effective options of the subcommand as TOML and exits
+    The resolved options and the configuration file are kept for the provenance of the outputs
+*/
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use clap::{Command, CommandFactory, FromArgMatches};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use super::get_args::Cli;
+
+// options that are not part of the configuration
+const SKIPPED_OPTIONS: [&str; 4] = ["help", "version", "config", "print_config"];
+
+// options of the subcommand after merging the configuration and the command line
+pub struct RunConfiguration {
+    pub subcommand: String,
+    pub options: Vec<ResolvedOption>,
+    pub config: Option<PathBuf>,
+}
+
+pub struct ResolvedOption {
+    // long option name
+    pub name: String,
+    // values as given, "true" or "false" for flags
+    pub values: Vec<String>,
+    pub flag: bool,
+    pub multiple: bool,
+}
+
+static RUN_CONFIGURATION: OnceLock<RunConfiguration> = OnceLock::new();
+
+// resolved options of the run, set by parse_args
+pub fn run_configuration() -> Option<&'static RunConfiguration> {
+    RUN_CONFIGURATION.get()
+}
+
+pub fn parse_args() -> Cli {
+    let mut command = Cli::command();
+    command.build();
+    let args = config_args(&mut command, std::env::args_os().collect());
+    let matches = command.clone().get_matches_from(args);
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        let subcommand = command.find_subcommand(name).expect("Subcommand of the parser");
+        let run_configuration = RunConfiguration {
+            subcommand: name.to_string(),
+            options: resolved_options(subcommand, sub_matches),
+            config: matches.get_one::<PathBuf>("config").cloned(),
+        };
+        if matches.get_flag("print_config") {
+            print!("{}", effective_config(&run_configuration));
+            std::process::exit(0);
+        }
+        RUN_CONFIGURATION.set(run_configuration).ok();
+    }
+    Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+// command line with the options of the configuration that are not given on it
+fn config_args(command: &mut Command, mut args: Vec<OsString>) -> Vec<OsString> {
+    // lenient first pass to find the subcommand, the configuration file and the options given
+    let matches = command.clone().ignore_errors(true).get_matches_from(args.clone());
+    if let (Some(config), Some((name, sub_matches))) = (matches.get_one::<PathBuf>("config"), matches.subcommand()) {
+        let subcommand = command.find_subcommand(name).expect("Subcommand of the parser").clone();
+        let options = match read_config(config, name) {
+            Ok(x) => x,
+            Err(e) => command.error(ErrorKind::Io, format!("Unable to read {:?} - {}", config, e)).exit()
+        };
+        for (key, values) in options {
+            let long = key.replace('_', "-");
+            let arg = match subcommand.get_arguments()
+                                .find(|x| x.get_long() == Some(long.as_str()) &&
+                                          !SKIPPED_OPTIONS.contains(&x.get_id().as_str())) {
+                Some(x) => x,
+                None => command.error(ErrorKind::UnknownArgument,
+                                      format!("Unknown option {} of the {} subcommand in {:?}", key, name, config))
+                                .exit()
+            };
+            if sub_matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+                continue
+            }
+            if !arg.get_action().takes_values() {
+                if values.iter().any(|x| x == "true") {
+                    args.push(OsString::from(format!("--{long}")));
+                }
+            } else if arg.get_num_args().map_or(1, |x| x.max_values()) > 1 {
+                args.push(OsString::from(format!("--{long}")));
+                args.extend(values.iter().map(OsString::from));
+            } else {
+                values.iter().for_each(|x| args.push(OsString::from(format!("--{long}={x}"))));
+            }
+        }
+    }
+    args
+}
+
+// options of the configuration for the subcommand, as command line values
+fn read_config(path: &Path, subcommand: &str) -> Result<BTreeMap<String, Vec<String>>, String> {
+    let content = read_to_string(path).map_err(|e| e.to_string())?;
+    let yaml = path.extension().is_some_and(|x| x == "yaml" || x == "yml");
+    let (top_level, section) = if yaml {
+        let document: serde_yaml::Mapping = serde_yaml::from_str(&content).map_err(|e| e.to_string())?;
+        yaml_options(&document, subcommand)?
+    } else {
+        let document: toml::Table = content.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        toml_options(&document, subcommand)?
+    };
+    // keys of the subcommand table override the top level keys
+    let mut options: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    top_level.into_iter().chain(section).for_each(|(key, values)| {
+        options.insert(key.replace('-', "_"), values);
+    });
+    Ok(options)
+}
+
+type Options = Vec<(String, Vec<String>)>;
+
+fn toml_options(document: &toml::Table, subcommand: &str) -> Result<(Options, Options), String> {
+    fn values(key: &str, value: &toml::Value) -> Result<Vec<String>, String> {
+        match value {
+            toml::Value::String(x) => Ok(vec![x.clone()]),
+            toml::Value::Array(x) => x.iter().map(|x| values(key, x).map(|x| x.concat())).collect(),
+            toml::Value::Table(_) => Err(format!("Unexpected table {}", key)),
+            x => Ok(vec![x.to_string()])
+        }
+    }
+    let mut top_level = Vec::new();
+    let mut section = Vec::new();
+    for (key, value) in document {
+        match value {
+            toml::Value::Table(table) if key == subcommand => for (key, value) in table {
+                section.push((key.clone(), values(key, value)?));
+            },
+            toml::Value::Table(_) => {},
+            value => top_level.push((key.clone(), values(key, value)?))
+        }
+    }
+    Ok((top_level, section))
+}
+
+fn yaml_options(document: &serde_yaml::Mapping, subcommand: &str) -> Result<(Options, Options), String> {
+    fn values(key: &str, value: &serde_yaml::Value) -> Result<Vec<String>, String> {
+        match value {
+            serde_yaml::Value::Null => Ok(Vec::new()),
+            serde_yaml::Value::Bool(x) => Ok(vec![x.to_string()]),
+            serde_yaml::Value::Number(x) => Ok(vec![x.to_string()]),
+            serde_yaml::Value::String(x) => Ok(vec![x.clone()]),
+            serde_yaml::Value::Sequence(x) => x.iter().map(|x| values(key, x).map(|x| x.concat())).collect(),
+            _ => Err(format!("Unexpected mapping {}", key))
+        }
+    }
+    let mut top_level = Vec::new();
+    let mut section = Vec::new();
+    for (key, value) in document {
+        let key = match key.as_str() {
+            Some(x) => x,
+            None => return Err(String::from("Keys must be option names"))
+        };
+        match value {
+            serde_yaml::Value::Mapping(mapping) if key == subcommand => for (key, value) in mapping {
+                let key = key.as_str().ok_or_else(|| String::from("Keys must be option names"))?;
+                section.push((key.to_string(), values(key, value)?));
+            },
+            serde_yaml::Value::Mapping(_) => {},
+            value => top_level.push((key.to_string(), values(key, value)?))
+        }
+    }
+    Ok((top_level, section))
+}
+
+// the options of the subcommand with a value, from the configuration, the command line or the defaults
+fn resolved_options(subcommand: &Command, matches: &clap::ArgMatches) -> Vec<ResolvedOption> {
+    subcommand.get_arguments()
+        .filter(|x| !SKIPPED_OPTIONS.contains(&x.get_id().as_str()))
+        .filter_map(|arg| {
+            let (long, raw) = match (arg.get_long(), matches.get_raw(arg.get_id().as_str())) {
+                (Some(long), Some(raw)) => (long, raw),
+                _ => return None
+            };
+            Some(ResolvedOption {
+                name: long.to_string(),
+                values: raw.map(|x| x.to_string_lossy().to_string()).collect(),
+                flag: !arg.get_action().takes_values(),
+                multiple: matches!(arg.get_action(), clap::ArgAction::Append) ||
+                          arg.get_num_args().map_or(1, |x| x.max_values()) > 1,
+            })
+        })
+        .collect()
+}
+
+// the resolved options as a TOML table of the subcommand
+fn effective_config(run_configuration: &RunConfiguration) -> String {
+    let mut config = format!("# effective configuration of the {} subcommand\n[{}]\n",
+                             run_configuration.subcommand, run_configuration.subcommand);
+    run_configuration.options.iter().for_each(|option| {
+        let values: Vec<String> = option.values.iter()
+                                    .map(|x| if option.flag || is_number(x) {
+                                        x.clone()
+                                    } else {
+                                        toml::Value::String(x.clone()).to_string()
+                                    })
+                                    .collect();
+        if option.multiple {
+            config += &format!("{} = [{}]\n", option.name, values.join(", "));
+        } else if let Some(value) = values.first() {
+            config += &format!("{} = {}\n", option.name, value);
+        }
+    });
+    config
+}
+
+// numbers are written without quotes in the configuration and the provenance
+pub fn is_number(raw: &str) -> bool {
+    raw.parse::<f64>().is_ok() && raw.chars().all(|x| x.is_ascii_digit() || ".-+eE".contains(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{remove_file, write};
+    use clap::Parser;
+    use crate::bin_utils::get_args::{Command as Subcommand, Mode};
+
+    const TOML_CONFIG: &str = "mode = \"standard\"\nthreshold = 0.5\npopgen = true\n\n\
+                               [entropy]\nthreshold = 0.9\ninfile = [\"a.fasta\", \"b.fasta\"]\n\n\
+                               [stats]\nthreshold = 0.1\n";
+    const YAML_CONFIG: &str = "mode: standard\nthreshold: 0.5\npopgen: true\n\
+                               entropy:\n  threshold: 0.9\n  infile: [a.fasta, b.fasta]\n\
+                               stats:\n  threshold: 0.1\n";
+
+    // configuration written to the temporary directory, removed when dropped
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(name: &str, content: &str) -> ConfigFile {
+            let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+            write(&path, content).expect("Unable to write the configuration");
+            ConfigFile(path)
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            remove_file(&self.0).ok();
+        }
+    }
+
+    fn option<'a>(options: &'a BTreeMap<String, Vec<String>>, key: &str) -> Vec<&'a str> {
+        options[key].iter().map(|x| x.as_str()).collect()
+    }
+
+    #[test]
+    fn subcommand_table_overrides_the_top_level() {
+        let config = ConfigFile::new("config.toml", TOML_CONFIG);
+        let options = read_config(&config.0, "entropy").unwrap();
+        assert_eq!(option(&options, "mode"), ["standard"]);
+        assert_eq!(option(&options, "threshold"), ["0.9"]);
+        assert_eq!(option(&options, "popgen"), ["true"]);
+        assert_eq!(option(&options, "infile"), ["a.fasta", "b.fasta"]);
+        // tables of the other subcommands are ignored
+        let options = read_config(&config.0, "stats").unwrap();
+        assert_eq!(option(&options, "threshold"), ["0.1"]);
+        assert!(!options.contains_key("infile"));
+    }
+
+    #[test]
+    fn yaml_matches_toml() {
+        let toml_config = ConfigFile::new("parity.toml", TOML_CONFIG);
+        let yaml_config = ConfigFile::new("parity.yaml", YAML_CONFIG);
+        ["entropy", "stats", "consensus"].iter().for_each(|x| {
+            assert_eq!(read_config(&yaml_config.0, x).unwrap(), read_config(&toml_config.0, x).unwrap());
+        });
+    }
+
+    #[test]
+    fn command_line_overrides_the_configuration() {
+        let config = ConfigFile::new("override.toml", TOML_CONFIG);
+        let mut command = Cli::command();
+        command.build();
+        let args = ["calculate_entropy", "--config", &config.0.to_string_lossy(), "entropy",
+                    "-i", "c.fasta", "--threshold", "0.7"].map(OsString::from).to_vec();
+        let cli = Cli::parse_from(config_args(&mut command, args));
+        match cli.command {
+            Subcommand::Entropy(args) => {
+                assert_eq!(args.input.input_alignment, [PathBuf::from("c.fasta")]);
+                assert_eq!(args.counting.threshold, 0.7);
+                assert!(args.counting.mode == Mode::Standard);
+                assert!(args.popgen);
+            },
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn effective_config_is_toml_of_the_subcommand() {
+        let resolved = |name: &str, values: &[&str], flag: bool, multiple: bool| ResolvedOption {
+            name: name.to_string(),
+            values: values.iter().map(|x| x.to_string()).collect(),
+            flag,
+            multiple,
+        };
+        let run_configuration = RunConfiguration {
+            subcommand: String::from("entropy"),
+            options: vec![resolved("infile", &["a.fasta", "b.fasta"], false, true),
+                          resolved("mode", &["standard"], false, false),
+                          resolved("threshold", &["0.8"], false, false),
+                          resolved("popgen", &["true"], true, false)],
+            config: None,
+        };
+        let config = effective_config(&run_configuration);
+        assert_eq!(config, "# effective configuration of the entropy subcommand\n[entropy]\n\
+                            infile = [\"a.fasta\", \"b.fasta\"]\nmode = \"standard\"\nthreshold = 0.8\npopgen = true\n");
+        let table: toml::Table = config.parse().unwrap();
+        let options = toml_options(&table, "entropy").unwrap().1;
+        assert_eq!(options.len(), 4);
+    }
+
+    #[test]
+    fn numbers_are_written_without_quotes() {
+        ["0.8", "16", "-1e3"].iter().for_each(|x| assert!(is_number(x), "{x}"));
+        ["standard", "1-5", "inf", "NaN", ""].iter().for_each(|x| assert!(!is_number(x), "{x}"));
+    }
+}
@@ -0,0 +1,237 @@
+/*
+    Consensus sequence from the column counts
+        Frequencies are taken over all sequences of the alignment
+            counted in 'All' mode, N and ambiguity codes are kept apart from the gaps
+        Gap ("-" and ".") more frequent than every base and the ambiguous notations: consensus is a gap
+            gap positions are left out with --drop-gaps
+        Ambiguous notations (N, IUPAC codes) more frequent than every base: N
+        Majority rule
+            most frequent of A, T, G, C, ties are written as their IUPAC code
+        IUPAC rule
+            IUPAC code of all bases with a frequency of at least the ambiguity threshold
+        Frequency of the consensus base(s) below the minimum frequency: N
+        Write the consensus as fasta, and optionally the support of every position
+    The consensus subcommand counts the alignment in 'All' mode and writes only the consensus, and the provenance
+        with --provenance
+        the entropy subcommand counts it again in 'All' mode when run in 'Standard' mode
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{create_output, initialise_structs, main_output_name, metric_output_name,
+                               notation_counts, open_output, output_name, process_genomes};
+use super::genetic_code::iupac_code;
+use super::get_args::{ConsensusArgs, ConsensusOptions, ConsensusRule, CountingOptions, Mode, OutputOptions};
+use super::provenance::write_provenance;
+
+const BASES: &str = "ATGC";
+const GAPS: &str = "-.";
+const FASTA_LINE_LENGTH: usize = 60;
+
+pub struct ConsensusBase {
+    pub base: char,
+    // frequency of the bases making up the consensus
+    pub support: f64,
+    pub depth: f64,
+    pub gap_fraction: f64,
+}
+
+// outputs of the consensus subcommand for an alignment
+pub fn consensus_outputs(path: &Path, args: &ConsensusArgs) -> Vec<String> {
+    let mut out_file_names = vec![main_output_name(path, &args.output, "consensus.fasta")];
+    if args.consensus_options.consensus_support {
+        out_file_names.push(metric_output_name(path, &args.output, "consensus_support"));
+    }
+    if args.output.provenance {
+        out_file_names.push(metric_output_name(path, &args.output, "provenance.json"));
+    }
+    out_file_names
+}
+
+// consensus subcommand: count the bases of the alignment and write its consensus
+pub fn run_consensus(file: &mut FileBufferHelper, args: &ConsensusArgs) -> Result<(), String> {
+    let count_vec = initialise_structs(file, &Mode::All);
+    file.buffer_reset();
+    let (map_vec, genome_count, _) = process_genomes(count_vec, file, args.nproc, None);
+    let out_file_name = main_output_name(file.path, &args.output, "consensus.fasta");
+    write_consensus(&map_vec, genome_count, file, &args.consensus_options, &args.output, &out_file_name)?;
+    match args.output.provenance {
+        true => write_provenance(map_vec.len(), genome_count, file, &args.output),
+        false => Ok(())
+    }
+}
+
+pub fn report_consensus(map_vec: &[HashMap<char, f64>], genome_count: f64, file: &mut FileBufferHelper,
+                        counting: &CountingOptions, options: &ConsensusOptions,
+                        output: &OutputOptions) -> Result<(), String> {
+    let out_file_name = output_name(file, output, "consensus.fasta");
+    if counting.mode == Mode::All {
+        return write_consensus(map_vec, genome_count, file, options, output, &out_file_name)
+    }
+    // 'Standard' mode counts N and ambiguity codes as "." together with the "." gaps
+    // the second pass costs another read of the alignment and a count vector of the 'All' notations
+    let count_vec = initialise_structs(file, &Mode::All);
+    file.buffer_reset();
+    let (all_map_vec, genome_count, _) = process_genomes(count_vec, file, counting.nproc, None);
+    file.buffer_reset();
+    write_consensus(&all_map_vec, genome_count, file, options, output, &out_file_name)
+}
+
+fn write_consensus(map_vec: &[HashMap<char, f64>], genome_count: f64, file: &FileBufferHelper,
+                   options: &ConsensusOptions, output: &OutputOptions,
+                   out_file_name: &str) -> Result<(), String> {
+    let consensus = consensus_bases(map_vec, genome_count, options);
+    let sequence: String = consensus.iter()
+                                .filter(|x| !(options.drop_gaps && x.base == '-'))
+                                .map(|x| x.base)
+                                .collect();
+    info!("Consensus of {} positions", sequence.len());
+
+    info!("Consensus output file: {}", out_file_name);
+    let out_file = open_output(out_file_name, output)?;
+    let mut consensus_writer = BufWriter::new(out_file);
+    let stem = file.path.file_stem().map_or(String::from("alignment"),
+                                            |x| x.to_string_lossy().to_string());
+    writeln!(consensus_writer, ">{stem}_consensus").expect("Unable to write to file");
+    sequence.as_bytes().chunks(FASTA_LINE_LENGTH).for_each(|line| {
+        writeln!(consensus_writer, "{}", String::from_utf8_lossy(line))
+            .expect("Unable to write to file");
+    });
+
+    if options.consensus_support {
+        write_support(&consensus, file, options, output)?;
+    }
+    Ok(())
+}
+
+pub fn consensus_bases(map_vec: &[HashMap<char, f64>], genome_count: f64,
+                       options: &ConsensusOptions) -> Vec<ConsensusBase> {
+    map_vec.iter().map(|char_map| {
+        let base_counts = notation_counts(char_map, BASES);
+        let depth: f64 = base_counts.iter().sum();
+        let gaps: f64 = notation_counts(char_map, GAPS).iter().sum();
+        // N and the other ambiguity codes
+        let ambiguous = (genome_count - depth - gaps).max(0.0);
+        let max_count = base_counts.iter().copied().fold(0.0, f64::max);
+        let gap_fraction = gaps/genome_count;
+        if gaps > max_count && gaps >= ambiguous {
+            return ConsensusBase { base: '-', support: gap_fraction, depth, gap_fraction }
+        }
+        if ambiguous > max_count {
+            return ConsensusBase { base: 'N', support: ambiguous/genome_count, depth, gap_fraction }
+        }
+        // bases making up the consensus
+        let included: Vec<(char, f64)> = BASES.chars()
+            .zip(base_counts)
+            .filter(|(_, count)| *count > 0.0)
+            .filter(|(_, count)| match options.consensus_rule {
+                ConsensusRule::Majority => *count == max_count,
+                ConsensusRule::Iupac => count/genome_count >= options.ambiguity_threshold
+            })
+            .collect();
+        let support = included.iter().map(|(_, count)| count).sum::<f64>()/genome_count;
+        let base = if included.is_empty() || support < options.min_frequency {
+            'N'
+        } else {
+            iupac_code(&included.iter().map(|(base, _)| *base).collect::<Vec<char>>())
+        };
+        ConsensusBase { base, support, depth, gap_fraction }
+    }).collect()
+}
+
+fn write_support(consensus: &[ConsensusBase], file: &FileBufferHelper,
+                 options: &ConsensusOptions, output: &OutputOptions) -> Result<(), String> {
+    let out_file_name = output_name(file, output, "consensus_support");
+    info!("Consensus support output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, output)?;
+    let mut support_writer = BufWriter::new(out_file);
+    writeln!(support_writer, "Position{delim}Consensus_position{delim}Consensus{delim}\
+                              Support{delim}Depth{delim}Gap_fraction", delim=output.delimiter)
+        .expect("Unable to write to file");
+    let mut consensus_position = 0usize;
+    consensus.iter().enumerate().for_each(|(idx, x)| {
+        let dropped = options.drop_gaps && x.base == '-';
+        if !dropped {
+            consensus_position += 1;
+        }
+        writeln!(support_writer, "{pos}{delim}{consensus_pos}{delim}{base}{delim}{support}{delim}\
+                                  {depth}{delim}{gap_fraction}",
+                 pos=idx + 1,
+                 consensus_pos=if dropped { String::from("NA") } else { consensus_position.to_string() },
+                 base=x.base, support=x.support, depth=x.depth, gap_fraction=x.gap_fraction,
+                 delim=output.delimiter).expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    fn consensus(sequences: &[&str], options: &[&str]) -> String {
+        let cli = entropy_args(Path::new("a.fasta"), options);
+        let (map_vec, genome_count) = counts(sequences, &Mode::All);
+        consensus_bases(&map_vec, genome_count, &cli.consensus_options).iter().map(|x| x.base).collect()
+    }
+
+    #[test]
+    fn majority_ties_are_iupac_codes() {
+        assert_eq!(consensus(&["AAGT", "AATT", "ACGC", "ACTC"], &[]), "AMKY");
+        assert_eq!(consensus(&["ACGT", "ACGT", "AAGT"], &[]), "ACGT");
+    }
+
+    #[test]
+    fn iupac_code_at_the_ambiguity_threshold() {
+        let sequences = ["AAC", "AAC", "AAT", "GCG"];
+        // G at 0.25 is part of the code at the default threshold of 0.25
+        assert_eq!(consensus(&sequences, &["--consensus-rule", "iupac"]), "RMB");
+        let threshold = ["--consensus-rule", "iupac", "--ambiguity-threshold", "0.3"];
+        assert_eq!(consensus(&sequences, &threshold), "AAC");
+        let threshold = ["--consensus-rule", "iupac", "--ambiguity-threshold", "0.5"];
+        assert_eq!(consensus(&sequences, &threshold), "AAC");
+        let threshold = ["--consensus-rule", "iupac", "--ambiguity-threshold", "0.6"];
+        assert_eq!(consensus(&sequences, &threshold), "AAN");
+    }
+
+    #[test]
+    fn min_frequency_gives_n() {
+        let sequences = ["AAC", "AAC", "ACG", "AGT"];
+        assert_eq!(consensus(&sequences, &[]), "AAC");
+        // support of the consensus: 1, 0.5 and 0.5
+        assert_eq!(consensus(&sequences, &["--min-frequency", "0.5"]), "AAC");
+        assert_eq!(consensus(&sequences, &["--min-frequency", "0.6"]), "ANN");
+    }
+
+    #[test]
+    fn gaps_and_ambiguity_codes() {
+        // gaps and N more frequent than every base
+        assert_eq!(consensus(&["A-NA", "A-NC", "A-NG", "C-AT"], &[]), "A-NN");
+        assert_eq!(consensus(&["A-A", "A.A", "AAN"], &[]), "A-A");
+    }
+
+    #[test]
+    fn drop_gaps() {
+        let dir = TestDir::new("consensus");
+        let sequences = [("s1", "A-CT"), ("s2", "A-CT"), ("s3", "AGC-")];
+        let path = dir.alignment("a.fasta", &sequences);
+        let mut file = check_fasta(&path, true).unwrap();
+        let (map_vec, genome_count) = counts(&["A-CT", "A-CT", "AGC-"], &Mode::Standard);
+        let cli = entropy_args(&path, &["--consensus", "--consensus-support"]);
+        report_consensus(&map_vec, genome_count, &mut file, &cli.counting, &cli.consensus_options, &cli.output)
+            .unwrap();
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_consensus.fasta"), ">a_consensus\nA-CT\n");
+        let cli = entropy_args(&path, &["--consensus", "--consensus-support", "--drop-gaps"]);
+        report_consensus(&map_vec, genome_count, &mut file, &cli.counting, &cli.consensus_options, &cli.output)
+            .unwrap();
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_consensus.fasta"), ">a_consensus\nACT\n");
+        let support = dir.read("a.fasta_shannon_entropy_output_consensus_support");
+        let rows: Vec<&str> = support.lines().skip(1).collect();
+        assert_eq!(rows[1], format!("2,NA,-,{},1,{}", 2.0/3.0, 2.0/3.0));
+        assert_eq!(rows[2], "3,2,C,1,3,0");
+    }
+}
@@ -0,0 +1,240 @@
+/*
+    Convert a validated fasta alignment to another alignment format
+        Fasta: sequences wrapped at the line width
+        Phylip (relaxed, sequential): "sequences positions", then name and sequence per line
+            names up to the first whitespace
+        Nexus: DATA block with the dimensions, DNA datatype and gap "-", names quoted when needed
+        Clustal: interleaved blocks of the line width, names padded to the longest name
+    Residues are written as they are in the alignment
+*/
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{main_output_name, metric_output_name, open_output};
+use super::get_args::{AlignmentFormat, ConvertArgs};
+use super::provenance::write_provenance;
+
+fn alignment_metric(format: AlignmentFormat) -> String {
+    let extension = match format {
+        AlignmentFormat::Fasta => "fasta",
+        AlignmentFormat::Phylip => "phy",
+        AlignmentFormat::Nexus => "nex",
+        AlignmentFormat::Clustal => "aln"
+    };
+    format!("alignment.{extension}")
+}
+
+// outputs of the convert subcommand for an alignment
+pub fn conversion_outputs(path: &Path, args: &ConvertArgs) -> Vec<String> {
+    let mut out_file_names = vec![main_output_name(path, &args.output, &alignment_metric(args.to))];
+    if args.output.provenance {
+        out_file_names.push(metric_output_name(path, &args.output, "provenance.json"));
+    }
+    out_file_names
+}
+
+pub fn run_conversion(file: &mut FileBufferHelper, args: &ConvertArgs) -> Result<(), String> {
+    let mut records: Vec<(String, String)> = Vec::new();
+    file.buffer_reset();
+    while let Some((header, sequence)) = file.next_record() {
+        records.push((header.trim().to_string(), sequence));
+    }
+    file.buffer_reset();
+    let positions = records.first().map_or(0, |(_, sequence)| sequence.len());
+    let converted = match args.to {
+        AlignmentFormat::Fasta => fasta(&records, args.line_width),
+        AlignmentFormat::Phylip => phylip(&records, positions),
+        AlignmentFormat::Nexus => nexus(&records, positions, args.line_width),
+        AlignmentFormat::Clustal => clustal(&records, positions, args.line_width)
+    };
+
+    let out_file_name = main_output_name(file.path, &args.output, &alignment_metric(args.to));
+    info!("Converted alignment output file: {}", out_file_name);
+    let out_file = open_output(&out_file_name, &args.output)?;
+    let mut alignment_writer = BufWriter::new(out_file);
+    write!(alignment_writer, "{}", converted).expect("Unable to write to file");
+    match args.output.provenance {
+        true => write_provenance(positions, records.len() as f64, file, &args.output),
+        false => Ok(())
+    }
+}
+
+fn lines(sequence: &str, line_width: usize) -> impl Iterator<Item=&str> {
+    sequence.as_bytes().chunks(line_width).map(|x| std::str::from_utf8(x).unwrap_or(""))
+}
+
+fn fasta(records: &[(String, String)], line_width: usize) -> String {
+    let mut converted = String::new();
+    records.iter().for_each(|(header, sequence)| {
+        converted += &format!(">{header}\n");
+        lines(sequence, line_width).for_each(|x| converted += &format!("{x}\n"));
+    });
+    converted
+}
+
+// sequence name up to the first whitespace of the header
+fn short_name(header: &str) -> &str {
+    header.split_whitespace().next().unwrap_or("")
+}
+
+fn phylip(records: &[(String, String)], positions: usize) -> String {
+    let width = records.iter().map(|(header, _)| short_name(header).len()).max().unwrap_or(0);
+    let mut converted = format!("{} {}\n", records.len(), positions);
+    records.iter().for_each(|(header, sequence)| {
+        converted += &format!("{:width$} {}\n", short_name(header), sequence);
+    });
+    converted
+}
+
+// names with characters other than letters, digits, "_", "." and "|" are single quoted
+fn nexus_name(header: &str) -> String {
+    if !header.is_empty() && header.chars().all(|x| x.is_ascii_alphanumeric() || "_.|".contains(x)) {
+        header.to_string()
+    } else {
+        format!("'{}'", header.replace('\'', "''"))
+    }
+}
+
+fn nexus(records: &[(String, String)], positions: usize, line_width: usize) -> String {
+    let names: Vec<String> = records.iter().map(|(header, _)| nexus_name(header)).collect();
+    let width = names.iter().map(|x| x.len()).max().unwrap_or(0);
+    let mut converted = format!("#NEXUS\n\nBEGIN DATA;\n\tDIMENSIONS NTAX={} NCHAR={};\n\
+                                 \tFORMAT DATATYPE=DNA MISSING=? GAP=- INTERLEAVE;\n\tMATRIX\n",
+                                records.len(), positions);
+    (0..positions).step_by(line_width).for_each(|start| {
+        names.iter().zip(records).for_each(|(name, (_, sequence))| {
+            let end = (start + line_width).min(sequence.len());
+            converted += &format!("\t{:width$} {}\n", name, &sequence[start.min(end)..end]);
+        });
+        converted += "\n";
+    });
+    converted += "\t;\nEND;\n";
+    converted
+}
+
+fn clustal(records: &[(String, String)], positions: usize, line_width: usize) -> String {
+    let width = records.iter().map(|(header, _)| short_name(header).len()).max().unwrap_or(0);
+    let mut converted = String::from("CLUSTAL W multiple sequence alignment\n\n");
+    (0..positions).step_by(line_width).for_each(|start| {
+        converted += "\n";
+        records.iter().for_each(|(header, sequence)| {
+            let end = (start + line_width).min(sequence.len());
+            converted += &format!("{:width$}      {}\n", short_name(header), &sequence[start.min(end)..end]);
+        });
+    });
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::{Cli, Command};
+    use crate::bin_utils::testing::TestDir;
+
+    // names longer than the 10 characters of strict PHYLIP, with characters nexus has to quote
+    const ALIGNMENT: [(&str, &str); 3] = [("hCoV-19/England/MILK-9E05B3/2020", "ACGTACGTAC"),
+                                          ("s2", "ACG-ACNTRC"), ("it's_a_long_name", "TCGTAC-TA-")];
+
+    fn records() -> Vec<(String, String)> {
+        ALIGNMENT.iter().map(|(header, sequence)| (header.to_string(), sequence.to_string())).collect()
+    }
+
+    // append the residues of a name, keeping the order of the first appearance
+    fn append(records: &mut Vec<(String, String)>, name: &str, residues: &str) {
+        match records.iter_mut().find(|(x, _)| x == name) {
+            Some((_, sequence)) => *sequence += residues,
+            None => records.push((name.to_string(), residues.to_string()))
+        }
+    }
+
+    fn read_phylip(converted: &str) -> Vec<(String, String)> {
+        let mut lines = converted.lines();
+        let dimensions: Vec<usize> = lines.next().unwrap().split(' ').map(|x| x.parse().unwrap()).collect();
+        let records: Vec<(String, String)> = lines.map(|x| {
+            let (name, sequence) = x.split_once(' ').unwrap();
+            (name.to_string(), sequence.trim_start().to_string())
+        }).collect();
+        assert_eq!(dimensions, [records.len(), records[0].1.len()]);
+        records
+    }
+
+    fn read_nexus(converted: &str) -> Vec<(String, String)> {
+        let matrix = &converted[converted.find("MATRIX\n").unwrap() + 7..converted.find("\t;\nEND;").unwrap()];
+        let mut records = Vec::new();
+        matrix.lines().filter(|x| !x.is_empty()).for_each(|line| {
+            let line = line.trim_start_matches('\t');
+            let (name, residues) = match line.strip_prefix('\'') {
+                Some(quoted) => {
+                    // a quote of the name is doubled
+                    let end = quoted.match_indices('\'').map(|(idx, _)| idx)
+                                .find(|idx| !quoted[idx + 1..].starts_with('\'') &&
+                                            !quoted[..*idx].ends_with('\''))
+                                .unwrap();
+                    (quoted[..end].replace("''", "'"), &quoted[end + 1..])
+                },
+                None => {
+                    let (name, residues) = line.split_once(' ').unwrap();
+                    (name.to_string(), residues)
+                }
+            };
+            append(&mut records, &name, residues.trim_start());
+        });
+        records
+    }
+
+    fn read_clustal(converted: &str) -> Vec<(String, String)> {
+        let mut records = Vec::new();
+        converted.lines().skip(1).filter(|x| !x.is_empty()).for_each(|line| {
+            let (name, residues) = line.split_once(' ').unwrap();
+            append(&mut records, name, residues.trim_start());
+        });
+        records
+    }
+
+    #[test]
+    fn formats_round_trip_to_fasta() {
+        let records = records();
+        let expected = fasta(&records, 4);
+        assert_eq!(fasta(&read_phylip(&phylip(&records, 10)), 4), expected);
+        assert_eq!(fasta(&read_nexus(&nexus(&records, 10, 4)), 4), expected);
+        assert_eq!(fasta(&read_clustal(&clustal(&records, 10, 4)), 4), expected);
+    }
+
+    #[test]
+    fn long_names_are_kept_in_relaxed_phylip() {
+        let converted = phylip(&records(), 10);
+        assert_eq!(converted.lines().nth(1), Some("hCoV-19/England/MILK-9E05B3/2020 ACGTACGTAC"));
+        assert_eq!(converted.lines().nth(2).unwrap().len(), "hCoV-19/England/MILK-9E05B3/2020 ".len() + 10);
+        // headers are cut at the first whitespace
+        let records = vec![(String::from("s1 England 2020"), String::from("ACGT"))];
+        assert_eq!(phylip(&records, 4), "1 4\ns1 ACGT\n");
+    }
+
+    #[test]
+    fn nexus_names_are_quoted_when_needed() {
+        assert_eq!(nexus_name("EPI_ISL_1|2020.1"), "EPI_ISL_1|2020.1");
+        assert_eq!(nexus_name("hCoV-19/England"), "'hCoV-19/England'");
+        assert_eq!(nexus_name("it's"), "'it''s'");
+        assert_eq!(nexus_name(""), "''");
+    }
+
+    #[test]
+    fn converted_alignment_file() {
+        let dir = TestDir::new("convert");
+        let path = dir.alignment("a.fasta", &ALIGNMENT);
+        let args = ["calculate_entropy", "convert", "-i", path.to_str().unwrap(), "--to", "clustal"];
+        let args = match Cli::parse_from(args).command {
+            Command::Convert(x) => x,
+            _ => unreachable!()
+        };
+        let mut file = check_fasta(&path, true).unwrap();
+        run_conversion(&mut file, &args).unwrap();
+        assert_eq!(dir.files(), ["a.fasta", "a.fasta_shannon_entropy_output_alignment.aln"]);
+        let converted = dir.read("a.fasta_shannon_entropy_output_alignment.aln");
+        assert_eq!(read_clustal(&converted), records());
+    }
+}
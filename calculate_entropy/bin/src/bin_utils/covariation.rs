@@ -0,0 +1,230 @@
+/*
+    Mutual information between pairs of alignment columns
+        Restrict to valid columns with Shannon entropy above the cutoff
+        Read the selected columns of every sequence
+            notations outside the considered set are treated as missing
+        For every pair of columns, in parallel over the thread pool
+            joint and marginal frequencies over sequences informative at both columns
+            MI = sum p(a,b) log_2 (p(a,b) / p(a)p(b))
+        Average product correction (Dunn et al. 2008)
+            MI_APC(i,j) = MI(i,j) - MI(i,.) MI(j,.) / MI(.,.)
+        Report the top pairs by MI_APC
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::sync::{mpsc, Arc};
+use threadpool::ThreadPool;
+use log::{debug, info, warn};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{considered_notations, create_output, output_name, valid_entropies};
+use super::get_args::EntropyArgs;
+
+pub const MISSING_NOTATION: u8 = u8::MAX;
+
+struct PairInformation {
+    first: usize,
+    second: usize,
+    shared: usize,
+    mutual_information: f64,
+}
+
+pub fn report_mutual_information(map_vec: &[HashMap<char, f64>], genome_count: f64,
+                                 file: &mut FileBufferHelper, cli: &EntropyArgs) -> Result<(), String> {
+    let entropies = valid_entropies(map_vec, genome_count, &cli.counting);
+    let selected: Vec<usize> = entropies.iter().enumerate()
+                                .filter_map(|(idx, x)| match x {
+                                    Some(entropy) if *entropy > 0.0 &&
+                                        *entropy >= cli.mi_entropy_cutoff => Some(idx),
+                                    _ => None
+                                })
+                                .collect();
+    info!("{} positions above the entropy cutoff of {} considered for mutual information",
+        selected.len(), cli.mi_entropy_cutoff);
+    if selected.len() < 3 {
+        warn!("At least 3 positions are needed for the average product correction. \
+               Mutual information not reported.");
+        return Ok(())
+    }
+    let notations = considered_notations(&cli.counting.mode);
+    let columns = Arc::new(collect_columns(file, &selected, notations));
+    let pairs = pairwise_information(columns, notations.len(), cli.counting.nproc);
+
+    let mut corrected: Vec<(&PairInformation, f64)> = pairs.iter()
+                                                    .zip(average_product_correction(&pairs, selected.len()))
+                                                    .collect();
+    corrected.sort_by(|a, b| b.1.total_cmp(&a.1));
+    corrected.truncate(cli.mi_top);
+
+    let out_file_name = output_name(file, &cli.output, "mutual_information");
+    info!("Mutual information output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut mi_writer = BufWriter::new(out_file);
+    writeln!(mi_writer, "Position_1{delim}Position_2{delim}Shannon_entropy_1{delim}\
+                         Shannon_entropy_2{delim}Shared_sequences{delim}MI{delim}MI_APC",
+             delim=cli.output.delimiter).expect("Unable to write to file");
+    corrected.iter().for_each(|(pair, mi_apc)| {
+        let (first, second) = (selected[pair.first], selected[pair.second]);
+        writeln!(mi_writer, "{pos_1}{delim}{pos_2}{delim}{entropy_1}{delim}{entropy_2}{delim}\
+                             {shared}{delim}{mi}{delim}{mi_apc}",
+                 pos_1=first + 1, pos_2=second + 1,
+                 entropy_1=entropies[first].unwrap(), entropy_2=entropies[second].unwrap(),
+                 shared=pair.shared, mi=pair.mutual_information, delim=cli.output.delimiter)
+            .expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+pub fn collect_columns(file: &mut FileBufferHelper, selected: &[usize],
+                       notations: &str) -> Vec<Vec<u8>> {
+    // notation index of every sequence at the selected columns
+    // one vector per column, MISSING_NOTATION for notations not considered
+    let lookup: HashMap<char, u8> = notations.chars()
+                                        .enumerate()
+                                        .map(|(idx, x)| (x, idx as u8))
+                                        .collect();
+    let mut columns: Vec<Vec<u8>> = vec![Vec::new(); selected.len()];
+    file.buffer_reset();
+    while let Some((_, genome)) = file.next_record() {
+        let genome = genome.as_bytes();
+        selected.iter().zip(columns.iter_mut()).for_each(|(&position, column)| {
+            let notation = genome.get(position)
+                            .and_then(|x| lookup.get(&(*x as char).to_ascii_uppercase()))
+                            .copied()
+                            .unwrap_or(MISSING_NOTATION);
+            column.push(notation);
+        });
+    }
+    file.buffer_reset();
+    debug!("{} columns collected", columns.len());
+    columns
+}
+
+fn pairwise_information(columns: Arc<Vec<Vec<u8>>>, n_notations: usize,
+                        nproc: usize) -> Vec<PairInformation> {
+    // one job per column, pairing it with all following columns
+    let pool = ThreadPool::new(nproc);
+    let (sender, receiver) = mpsc::channel();
+    for first in 0..columns.len() {
+        let columns_clone = Arc::clone(&columns);
+        let sender_clone = sender.clone();
+        pool.execute(move || {
+            let row: Vec<PairInformation> = (first + 1..columns_clone.len())
+                .map(|second| {
+                    let (shared, mutual_information) = mutual_information(
+                        &columns_clone[first], &columns_clone[second], n_notations);
+                    PairInformation { first, second, shared, mutual_information }
+                })
+                .collect();
+            sender_clone.send(row).expect("Unable to send mutual information");
+        });
+    }
+    drop(sender);
+    let pairs: Vec<PairInformation> = receiver.iter().flatten().collect();
+    pool.join();
+    info!("Mutual information calculated for {} pairs", pairs.len());
+    pairs
+}
+
+fn average_product_correction(pairs: &[PairInformation], n_columns: usize) -> Vec<f64> {
+    // MI_APC of every pair, in the order of the pairs
+    let mut column_sums = vec![0.0f64; n_columns];
+    pairs.iter().for_each(|pair| {
+        column_sums[pair.first] += pair.mutual_information;
+        column_sums[pair.second] += pair.mutual_information;
+    });
+    let overall_mean = column_sums.iter().sum::<f64>()/(n_columns * (n_columns - 1)) as f64;
+    let column_means: Vec<f64> = column_sums.iter()
+                                    .map(|x| x/(n_columns - 1) as f64)
+                                    .collect();
+    pairs.iter().map(|pair| {
+        let apc = if overall_mean > 0.0 {
+            column_means[pair.first] * column_means[pair.second] / overall_mean
+        } else {
+            0.0
+        };
+        pair.mutual_information - apc
+    }).collect()
+}
+
+fn mutual_information(first: &[u8], second: &[u8], n_notations: usize) -> (usize, f64) {
+    // MI over sequences with considered notations at both columns
+    let mut joint = vec![0.0f64; n_notations * n_notations];
+    let mut shared = 0usize;
+    first.iter().zip(second).for_each(|(&a, &b)| {
+        if a != MISSING_NOTATION && b != MISSING_NOTATION {
+            joint[a as usize * n_notations + b as usize] += 1.0;
+            shared += 1;
+        }
+    });
+    if shared == 0 {
+        return (0, 0.0)
+    }
+    let total = shared as f64;
+    let mut marginal_first = vec![0.0f64; n_notations];
+    let mut marginal_second = vec![0.0f64; n_notations];
+    joint.iter().enumerate().for_each(|(idx, count)| {
+        marginal_first[idx / n_notations] += count;
+        marginal_second[idx % n_notations] += count;
+    });
+    let information = joint.iter().enumerate()
+                        .filter(|(_, &count)| count > 0.0)
+                        .map(|(idx, &count)| {
+                            let p_joint = count/total;
+                            let p_first = marginal_first[idx / n_notations]/total;
+                            let p_second = marginal_second[idx % n_notations]/total;
+                            p_joint * (p_joint/(p_first * p_second)).log(2.0)
+                        })
+                        .sum::<f64>();
+    (shared, information.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(first: usize, second: usize, mutual_information: f64) -> PairInformation {
+        PairInformation { first, second, shared: 0, mutual_information }
+    }
+
+    #[test]
+    fn mutual_information_of_linked_columns() {
+        // two equally frequent states that always co-occur share one bit
+        let (shared, information) = mutual_information(&[0, 0, 1, 1], &[2, 2, 3, 3], 4);
+        assert_eq!(shared, 4);
+        assert!((information - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mutual_information_of_independent_columns() {
+        let (shared, information) = mutual_information(&[0, 0, 1, 1], &[0, 1, 0, 1], 4);
+        assert_eq!(shared, 4);
+        assert_eq!(information, 0.0);
+    }
+
+    #[test]
+    fn mutual_information_skips_missing_notations() {
+        let first = [0, 1, MISSING_NOTATION, 0];
+        let second = [0, 1, 1, MISSING_NOTATION];
+        let (shared, information) = mutual_information(&first, &second, 4);
+        assert_eq!(shared, 2);
+        assert!((information - 1.0).abs() < 1e-12);
+        assert_eq!(mutual_information(&[MISSING_NOTATION], &[0], 4), (0, 0.0));
+    }
+
+    #[test]
+    fn average_product_correction_known_values() {
+        // column means 0.5, 0.75 and 0.25, overall mean 0.5
+        let pairs = [pair(0, 1, 1.0), pair(0, 2, 0.0), pair(1, 2, 0.5)];
+        let corrected = average_product_correction(&pairs, 3);
+        [0.25, -0.25, 0.125].iter().zip(&corrected).for_each(|(expected, x)| {
+            assert!((expected - x).abs() < 1e-12);
+        });
+    }
+
+    #[test]
+    fn average_product_correction_without_information() {
+        let pairs = [pair(0, 1, 0.0), pair(0, 2, 0.0), pair(1, 2, 0.0)];
+        assert_eq!(average_product_correction(&pairs, 3), vec![0.0, 0.0, 0.0]);
+    }
+}
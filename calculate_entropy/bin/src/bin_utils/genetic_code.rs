@@ -0,0 +1,236 @@
+/*
+    Translate codons with NCBI translation tables
+        Tables are stored as the 64 amino acids of the NCBI gc.prt listing
+            codon order TTT, TTC, TTA, TTG, TCT, ... GGG (bases ordered T, C, A, G)
+        Ambiguous codons
+            expand every IUPAC code into the bases it stands for
+            translate only if all expansions give the same amino acid
+        Codons are translated within the reading frame
+            alternative start codons give their amino acid, e.g. TTG is L in table 11, not M
+*/
+
+const BASE_ORDER: &str = "TCAG";
+
+// NCBI table ID and amino acids in TCAG codon order
+// tables with context dependent stop codons (27, 28, 31) are not included
+const TRANSLATION_TABLES: [(u8, &str); 23] = [
+    (1, "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (2, "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG"),
+    (3, "FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (4, "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (5, "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG"),
+    (6, "FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (9, "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG"),
+    (10, "FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (11, "FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (12, "FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (13, "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG"),
+    (14, "FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG"),
+    (16, "FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (21, "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG"),
+    (22, "FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (23, "FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (24, "FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG"),
+    (25, "FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (26, "FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (29, "FFLLSSSSYYYYCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (30, "FFLLSSSSYYEECC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (32, "FFLLSSSSYY*WCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (33, "FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG"),
+];
+
+pub struct GeneticCode {
+    amino_acids: Vec<char>,
+}
+
+impl GeneticCode {
+    pub fn new(table: u8) -> Option<GeneticCode> {
+        TRANSLATION_TABLES.iter()
+            .find(|(id, _)| *id == table)
+            .map(|(_, amino_acids)| GeneticCode { amino_acids: amino_acids.chars().collect() })
+    }
+
+    pub fn translate(&self, codon: &[char]) -> Option<char> {
+        // translate an unambiguous codon of A, C, G, T/U
+        if codon.len() != 3 {
+            return None
+        }
+        let mut idx = 0usize;
+        for base in codon {
+            let base = match base.to_ascii_uppercase() {
+                'U' => 'T',
+                x => x
+            };
+            idx = idx * 4 + BASE_ORDER.find(base)?;
+        }
+        Some(self.amino_acids[idx])
+    }
+
+    pub fn translate_ambiguous(&self, codon: &[char]) -> Option<char> {
+        // translate a codon with IUPAC ambiguity codes
+        // None if the expansions do not agree on a single amino acid
+        if codon.len() != 3 {
+            return None
+        }
+        let mut amino_acid: Option<char> = None;
+        for first in expand_base(codon[0])?.chars() {
+            for second in expand_base(codon[1])?.chars() {
+                for third in expand_base(codon[2])?.chars() {
+                    let translated = self.translate(&[first, second, third])?;
+                    match amino_acid {
+                        None => amino_acid = Some(translated),
+                        Some(x) if x != translated => return None,
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+        amino_acid
+    }
+}
+
+pub fn supported_tables() -> Vec<u8> {
+    TRANSLATION_TABLES.iter().map(|(id, _)| *id).collect()
+}
+
+pub fn expand_base(base: char) -> Option<&'static str> {
+    // bases represented by an IUPAC nucleotide code
+    match base.to_ascii_uppercase() {
+        'A' => Some("A"),
+        'C' => Some("C"),
+        'G' => Some("G"),
+        'T' | 'U' => Some("T"),
+        'R' => Some("AG"),
+        'Y' => Some("CT"),
+        'S' => Some("CG"),
+        'W' => Some("AT"),
+        'K' => Some("GT"),
+        'M' => Some("AC"),
+        'B' => Some("CGT"),
+        'D' => Some("AGT"),
+        'H' => Some("ACT"),
+        'V' => Some("ACG"),
+        'N' => Some("ACGT"),
+        _ => None
+    }
+}
+
+pub fn iupac_code(bases: &[char]) -> char {
+    // IUPAC nucleotide code representing a set of bases
+    let has = |x: char| bases.contains(&x);
+    match (has('A'), has('C'), has('G'), has('T')) {
+        (true, false, false, false) => 'A',
+        (false, true, false, false) => 'C',
+        (false, false, true, false) => 'G',
+        (false, false, false, true) => 'T',
+        (true, false, true, false) => 'R',
+        (false, true, false, true) => 'Y',
+        (false, true, true, false) => 'S',
+        (true, false, false, true) => 'W',
+        (false, false, true, true) => 'K',
+        (true, true, false, false) => 'M',
+        (false, true, true, true) => 'B',
+        (true, false, true, true) => 'D',
+        (true, true, false, true) => 'H',
+        (true, true, true, false) => 'V',
+        _ => 'N'
+    }
+}
+
+pub fn complement(base: char) -> char {
+    // complement of an IUPAC nucleotide code, gaps are returned as is
+    match base.to_ascii_uppercase() {
+        'A' => 'T',
+        'T' | 'U' => 'A',
+        'C' => 'G',
+        'G' => 'C',
+        'R' => 'Y',
+        'Y' => 'R',
+        'K' => 'M',
+        'M' => 'K',
+        'B' => 'V',
+        'V' => 'B',
+        'D' => 'H',
+        'H' => 'D',
+        x => x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(code: &GeneticCode, codon: &str) -> Option<char> {
+        code.translate(&codon.chars().collect::<Vec<char>>())
+    }
+
+    fn stop_codons(code: &GeneticCode) -> Vec<String> {
+        let bases: Vec<char> = BASE_ORDER.chars().collect();
+        let mut stops: Vec<String> = (0..64usize)
+                                        .map(|x| [x/16, x/4 % 4, x % 4].iter().map(|&y| bases[y]).collect())
+                                        .filter(|x: &String| translate(code, x) == Some('*'))
+                                        .collect();
+        stops.sort();
+        stops
+    }
+
+    #[test]
+    fn every_table_has_64_codons() {
+        TRANSLATION_TABLES.iter().for_each(|(id, amino_acids)| assert_eq!(amino_acids.len(), 64, "{id}"));
+    }
+
+    #[test]
+    fn standard_code() {
+        let code = GeneticCode::new(1).unwrap();
+        assert_eq!(stop_codons(&code), ["TAA", "TAG", "TGA"]);
+        assert_eq!(translate(&code, "ATG"), Some('M'));
+        assert_eq!(translate(&code, "AUG"), Some('M'));
+        assert_eq!(translate(&code, "ATA"), Some('I'));
+        assert_eq!(translate(&code, "AGA"), Some('R'));
+        assert_eq!(translate(&code, "GGG"), Some('G'));
+        // alternative starts of table 1 are translated as their amino acid
+        assert_eq!(translate(&code, "TTG"), Some('L'));
+        assert_eq!(translate(&code, "CTG"), Some('L'));
+    }
+
+    #[test]
+    fn vertebrate_mitochondrial_code() {
+        let code = GeneticCode::new(2).unwrap();
+        assert_eq!(stop_codons(&code), ["AGA", "AGG", "TAA", "TAG"]);
+        assert_eq!(translate(&code, "TGA"), Some('W'));
+        assert_eq!(translate(&code, "ATA"), Some('M'));
+        // alternative starts ATT, ATC and GTG keep their amino acid
+        assert_eq!(translate(&code, "ATT"), Some('I'));
+        assert_eq!(translate(&code, "GTG"), Some('V'));
+    }
+
+    #[test]
+    fn bacterial_code() {
+        let code = GeneticCode::new(11).unwrap();
+        assert_eq!(stop_codons(&code), ["TAA", "TAG", "TGA"]);
+        assert_eq!(translate(&code, "ATA"), Some('I'));
+        // alternative starts GTG, TTG, CTG, ATT, ATC and ATA keep their amino acid
+        [("GTG", 'V'), ("TTG", 'L'), ("CTG", 'L'), ("ATT", 'I'), ("ATC", 'I')].iter()
+            .for_each(|(codon, amino_acid)| assert_eq!(translate(&code, codon), Some(*amino_acid), "{codon}"));
+    }
+
+    #[test]
+    fn unsupported_table() {
+        assert!(GeneticCode::new(7).is_none());
+        assert!(!supported_tables().contains(&27));
+    }
+
+    #[test]
+    fn ambiguous_codons() {
+        let translate_ambiguous = |code: &GeneticCode, codon: &str|
+                                    code.translate_ambiguous(&codon.chars().collect::<Vec<char>>());
+        let standard = GeneticCode::new(1).unwrap();
+        let mitochondrial = GeneticCode::new(2).unwrap();
+        assert_eq!(translate_ambiguous(&standard, "TAR"), Some('*'));
+        assert_eq!(translate_ambiguous(&standard, "TRA"), Some('*'));
+        assert_eq!(translate_ambiguous(&mitochondrial, "TRA"), None);
+        assert_eq!(translate_ambiguous(&standard, "GGN"), Some('G'));
+        assert_eq!(translate_ambiguous(&standard, "ATN"), None);
+        assert_eq!(translate_ambiguous(&standard, "AT-"), None);
+    }
+}
@@ -1,28 +1,317 @@
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use super::genetic_code::supported_tables;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+    /// TOML (or YAML for .yaml/.yml) file of options of the subcommand, overridden by the command line
+    #[arg(long="config", global=true)]
+    pub config: Option<PathBuf>,
+    /// Print the effective options of the subcommand, from the command line, the configuration and the defaults, and exit
+    #[arg(long="print-config", global=true)]
+    pub print_config: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Check that the alignments are in fasta format with sequences of the same length
+    Validate(ValidateArgs),
+    /// Calculate the Shannon entropy at every position, with the optional reports
+    Entropy(Box<EntropyArgs>),
+    /// Write the summary statistics of the alignments
+    Stats(StatsArgs),
+    /// Write the consensus sequence of the alignments
+    Consensus(ConsensusArgs),
+    /// Convert the alignments to another alignment format
+    Convert(ConvertArgs),
+}
+
+// input alignments, shared by all subcommands
+#[derive(Args)]
+pub struct InputOptions {
     /// Path to Alignment file stored in fasta format
     #[arg(short='i', long="infile", required = true, action=clap::ArgAction::Append)]
     pub input_alignment: Vec<PathBuf>,
+}
+
+// counting of the notations at every position
+#[derive(Args)]
+pub struct CountingOptions {
     /// Keep tab of 'All' allowed DNA notations or only allow the 'Standard' ATGC. Recommended: 'Standard'
     #[arg(short='m', long="mode", value_enum, required = true)]
     pub mode: Mode,
     /// Set minimum percentage of 'Standard' ATGC notations to constitute the column. Default: 0.8.
     #[arg(short='t', long="threshold", value_parser=validate_percent, default_value_t=0.8)]
     pub threshold: f64,
+    /// Number of threads counting the sequences. Default: 16
+    #[arg(short='n', long="threads", default_value_t=16)]
+    pub nproc: usize,
+}
+
+// names and location of the output files
+#[derive(Args)]
+pub struct OutputOptions {
     /// Suffix to be appended to the filename when storing the file. Default: "shannon_entropy_output.csv"
     #[arg(short='s', long="output-suffix", default_value_t=String::from("shannon_entropy_output"))]
     pub output_suffix: String,
     /// Specify delimiter to separate position and entropy. Defalt: ","
     #[arg(short='d', long="delimiter", default_value_t=',')]
     pub delimiter: char,
-    /// Specify delimiter to separate position and entropy. Defalt: ","
+    /// Directory to store the output files in, created when missing. Default: directory of the input alignment
+    #[arg(long="outdir")]
+    pub outdir: Option<PathBuf>,
+    /// Name template of the output files holding {metric} and {ext}, and optionally {stem}, {name} and {suffix}, e.g. "{stem}.{metric}.{ext}", or "-" to write the main output (entropy table, statistics, consensus or converted alignment) to stdout. Default: <input>_<suffix>_<metric>
+    #[arg(long="output", value_parser=validate_output)]
+    pub name_template: Option<String>,
+    /// Write the provenance of the outputs (tool version, resolved options, input checksum, timestamp) as a JSON sidecar
+    #[arg(long="provenance")]
+    pub provenance: bool,
+    /// Refuse to overwrite existing output files, checked for all outputs before the first alignment is read
+    #[arg(long="no-clobber")]
+    pub no_clobber: bool,
+}
+
+#[derive(Args)]
+pub struct ConsensusOptions {
+    /// Call the consensus by 'Majority' rule, or as the 'Iupac' code of bases above the ambiguity threshold. Default: 'Majority'
+    #[arg(long="consensus-rule", value_enum, default_value_t=ConsensusRule::Majority)]
+    pub consensus_rule: ConsensusRule,
+    /// Minimum frequency of a base to be part of an IUPAC consensus. Default: 0.25
+    #[arg(long="ambiguity-threshold", value_parser=validate_percent, default_value_t=0.25)]
+    pub ambiguity_threshold: f64,
+    /// Minimum frequency of the consensus base(s), below which the consensus is N. Default: 0
+    #[arg(long="min-frequency", value_parser=validate_percent, default_value_t=0.0)]
+    pub min_frequency: f64,
+    /// Leave out positions where the consensus is a gap
+    #[arg(long="drop-gaps")]
+    pub drop_gaps: bool,
+    /// Write the support of the consensus at every position
+    #[arg(long="consensus-support")]
+    pub consensus_support: bool,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub input: InputOptions,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub input: InputOptions,
+    #[command(flatten)]
+    pub counting: CountingOptions,
+    #[command(flatten)]
+    pub output: OutputOptions,
+    /// Write the non-permissible characters found while counting to a JSON Lines log
+    #[arg(long="log")]
+    pub log: bool,
+}
+
+#[derive(Args)]
+pub struct ConsensusArgs {
+    #[command(flatten)]
+    pub input: InputOptions,
+    /// Number of threads counting the sequences. Default: 16
     #[arg(short='n', long="threads", default_value_t=16)]
-    pub nproc: usize
+    pub nproc: usize,
+    #[command(flatten)]
+    pub consensus_options: ConsensusOptions,
+    #[command(flatten)]
+    pub output: OutputOptions,
+}
+
+#[derive(Args)]
+pub struct ConvertArgs {
+    #[command(flatten)]
+    pub input: InputOptions,
+    /// Alignment format to convert to: 'Fasta', relaxed 'Phylip', 'Nexus' or 'Clustal'
+    #[arg(long="to", value_enum)]
+    pub to: AlignmentFormat,
+    /// Residues per line of the converted alignment. Default: 60
+    #[arg(long="line-width", value_parser=validate_positive, default_value_t=60)]
+    pub line_width: usize,
+    #[command(flatten)]
+    pub output: OutputOptions,
+}
+
+#[derive(Args)]
+pub struct EntropyArgs {
+    #[command(flatten)]
+    pub input: InputOptions,
+    #[command(flatten)]
+    pub counting: CountingOptions,
+    #[command(flatten)]
+    pub output: OutputOptions,
+    /// Format of the per position entropy output: 'Delimited' text with --delimiter, typed 'Csv' or 'Tsv', 'Jsonl', 'Arrow' IPC or 'Parquet'. Default: 'Delimited'
+    #[arg(long="output-format", value_enum, default_value_t=OutputFormat::Delimited)]
+    pub output_format: OutputFormat,
+    /// Decimal places of floating point values in the Csv, Tsv and Jsonl formats. Default: full precision
+    #[arg(long="precision")]
+    pub precision: Option<usize>,
+    /// Write the non-permissible characters found while counting to a JSON Lines log, also written with --popgen
+    #[arg(long="log")]
+    pub log: bool,
+    /// Write the run statistics of the alignment (sequences, valid positions, mean and median entropy, composition), also written with --popgen
+    #[arg(long="statistics")]
+    pub statistics: bool,
+    /// Header (or part of it) of the reference sequence in the alignment. Default: first sequence
+    #[arg(long="reference")]
+    pub reference: Option<String>,
+    /// GFF3 file in reference coordinates to annotate positions with feature and codon context
+    #[arg(long="gff")]
+    pub gff: Option<PathBuf>,
+    /// Report codon and amino acid entropy over the CDS features of the GFF3 file, or over the reading frame
+    #[arg(long="codon-entropy")]
+    pub codon_entropy: bool,
+    /// Classify alternative bases at coding positions as synonymous or non-synonymous, with a dN/dS-like summary per CDS
+    #[arg(long="synonymous")]
+    pub synonymous: bool,
+    /// Report mutual information with average product correction (MI-APC) between pairs of positions
+    #[arg(long="mutual-information")]
+    pub mutual_information: bool,
+    /// Minimum Shannon entropy of a position to be paired for mutual information. Default: 0.2
+    #[arg(long="mi-entropy-cutoff", default_value_t=0.2)]
+    pub mi_entropy_cutoff: f64,
+    /// Number of top pairs by MI-APC to report. Default: 1000
+    #[arg(long="mi-top", default_value_t=1000)]
+    pub mi_top: usize,
+    /// Report linkage disequilibrium (D, D', r^2) between the major and minor alleles of variable positions
+    #[arg(long="linkage")]
+    pub linkage: bool,
+    /// Minimum Shannon entropy of a position to be considered for linkage. Default: 0.2
+    #[arg(long="ld-entropy-cutoff", default_value_t=0.2)]
+    pub ld_entropy_cutoff: f64,
+    /// Maximum distance in alignment positions between pairs of positions for linkage. Default: no limit
+    #[arg(long="ld-max-distance")]
+    pub ld_max_distance: Option<usize>,
+    /// Write linkage as a 'Long' table of pairs, or as r^2 and D' 'Matrix' files. Default: 'Long'
+    #[arg(long="ld-format", value_enum, default_value_t=LdFormat::Long)]
+    pub ld_format: LdFormat,
+    /// Report nucleotide diversity, segregating sites, Watterson's theta and Tajima's D
+    #[arg(long="popgen")]
+    pub popgen: bool,
+    /// Size of sliding windows in alignment positions for windowed statistics
+    #[arg(long="window-size", value_parser=validate_positive)]
+    pub window_size: Option<usize>,
+    /// Step between sliding windows. Default: window size
+    #[arg(long="window-step", value_parser=validate_positive)]
+    pub window_step: Option<usize>,
+    /// Write the consensus sequence of the alignment in fasta format. In 'Standard' mode the alignment is read and counted a second time in 'All' mode
+    #[arg(long="consensus")]
+    pub consensus: bool,
+    #[command(flatten)]
+    pub consensus_options: ConsensusOptions,
+    /// Write a table of the alleles at every position with major and minor allele, MAF, singletons and doubletons
+    #[arg(long="alleles")]
+    pub alleles: bool,
+    /// Minimum minor allele frequency of a position to be reported in the allele table. Default: 0
+    #[arg(long="min-maf", value_parser=validate_percent, default_value_t=0.0)]
+    pub min_maf: f64,
+    /// Minimum count of an allele to be reported in the allele table. Default: 1
+    #[arg(long="min-allele-count", default_value_t=1)]
+    pub min_allele_count: usize,
+    /// Classify positions as constant, singleton or parsimony informative, with a summary of the classes
+    #[arg(long="site-classes")]
+    pub site_classes: bool,
+    /// Write the alignment of parsimony informative positions only
+    #[arg(long="informative-alignment", requires="site_classes")]
+    pub informative_alignment: bool,
+    /// Report transitions and transversions at every position, and the alignment wide mutation spectrum
+    #[arg(long="substitutions")]
+    pub substitutions: bool,
+    /// Count substitutions against the 'Reference' base, or the 'Consensus' (major allele). Default: 'Consensus'
+    #[arg(long="substitution-base", value_enum, default_value_t=SubstitutionBase::Consensus)]
+    pub substitution_base: SubstitutionBase,
+    /// Tab separated metadata with one row per sequence and a header line
+    #[arg(long="metadata")]
+    pub metadata: Option<PathBuf>,
+    /// Metadata column to report entropy per group, e.g. lineage, date or location.
+    /// Counts of every position are held for every group, memory grows with the number of groups
+    #[arg(long="group-by", requires="metadata")]
+    pub group_by: Option<String>,
+    /// Metadata column holding the sequence IDs. Default: first column
+    #[arg(long="metadata-id")]
+    pub metadata_id: Option<String>,
+    /// Separator of the fasta header fields used to find the sequence ID. Default: "|"
+    #[arg(long="id-separator", default_value_t='|')]
+    pub id_separator: char,
+    /// Field of the fasta header (1 based) holding the sequence ID. Default: 1
+    #[arg(long="id-field", value_parser=validate_positive, default_value_t=1)]
+    pub id_field: usize,
+    /// Report entropy per 'Week' (ISO 8601) or 'Month' of collection as a positions x time matrix
+    #[arg(long="time-bin", value_enum)]
+    pub time_bin: Option<TimeBin>,
+    /// Metadata column holding collection dates (YYYY-MM-DD). Default: date field of the fasta header
+    #[arg(long="date-column", requires="metadata")]
+    pub date_column: Option<String>,
+    /// Time bins with fewer sequences are flagged as 'Low_count' and their entropy is NA. Default: 10
+    #[arg(long="min-bin-size", default_value_t=10)]
+    pub min_bin_size: usize,
+    /// Alignment of the same length to compare entropy against, position by position.
+    /// Both alignments are held in memory, about one byte per sequence and position
+    #[arg(long="compare")]
+    pub compare: Option<PathBuf>,
+    /// Two groups of the --group-by column to compare entropy between
+    #[arg(long="compare-groups", num_args=2, value_names=["FIRST", "SECOND"], requires="group_by",
+          conflicts_with="compare")]
+    pub compare_groups: Option<Vec<String>>,
+    /// Number of label permutations to test entropy differences. Default: 1000
+    #[arg(long="permutations", value_parser=validate_positive, default_value_t=1000)]
+    pub permutations: usize,
+    /// Seed of the random number generator. Default: random, reported in the log
+    #[arg(long="seed")]
+    pub seed: Option<u64>,
+    /// Sample sizes (comma separated) to report the expected entropy of by repeated subsampling
+    #[arg(long="rarefaction", value_parser=validate_positive, value_delimiter=',')]
+    pub rarefaction: Vec<usize>,
+    /// Number of subsamples drawn for every sample size and position. Default: 100
+    #[arg(long="rarefaction-replicates", value_parser=validate_positive, default_value_t=100)]
+    pub rarefaction_replicates: usize,
+    /// Write bedGraph and WIG tracks of the entropy in reference coordinates
+    #[arg(long="tracks")]
+    pub tracks: bool,
+    /// Chromosome name of the tracks and VCF. Default: reference header up to the first whitespace
+    #[arg(long="chrom")]
+    pub chrom: Option<String>,
+    /// Write a BED track of the reference positions with at least this entropy
+    #[arg(long="high-entropy")]
+    pub high_entropy: Option<f64>,
+    /// Write a sites only VCF of the variable columns against the reference
+    #[arg(long="vcf")]
+    pub vcf: bool,
+    /// Plot the entropy profile as SVG, with the window mean when --window-size is given and CDS features of the GFF3 file
+    #[arg(long="plot")]
+    pub plot: bool,
+    /// Entropy of the horizontal threshold line of the plot
+    #[arg(long="plot-threshold", requires="plot")]
+    pub plot_threshold: Option<f64>,
+    /// Draw an SVG sequence logo of the alignment positions START-END (1 based, inclusive)
+    #[arg(long="logo", value_parser=validate_region, value_name="START-END")]
+    pub logo: Option<(usize, usize)>,
+    /// Logo of the 'Nucleotide' columns, or of the 'Protein' codons of the CDS features or reading frame. Default: 'Nucleotide'
+    #[arg(long="logo-alphabet", value_enum, default_value_t=LogoAlphabet::Nucleotide)]
+    pub logo_alphabet: LogoAlphabet,
+    /// Apply the small sample correction to the information content of the logo
+    #[arg(long="small-sample-correction", requires="logo")]
+    pub small_sample_correction: bool,
+    /// Write a self contained HTML report of the run next to the entropy output
+    #[arg(long="report")]
+    pub report: bool,
+    /// Number of the most variable positions listed in the report. Default: 20
+    #[arg(long="report-top", default_value_t=20)]
+    pub report_top: usize,
+    /// Reading frame (1, 2 or 3) over the reference when no GFF3 file is given. Default: 1
+    #[arg(long="frame", value_parser=clap::value_parser!(u8).range(1..=3), default_value_t=1)]
+    pub frame: u8,
+    /// NCBI translation table used to translate codons. Default: 1 (standard code)
+    #[arg(long="genetic-code", value_parser=validate_genetic_code, default_value_t=1)]
+    pub genetic_code: u8
 }
 
 const PERCENTAGE: RangeInclusive<f64> = 0f64..=1f64;
@@ -33,15 +322,105 @@ pub enum Mode {
     All
 }
 
-fn validate_percent(input_str: &str) -> Result<f64, String> {
-    let percent: f64 = input_str
-        .parse()
-        .unwrap_or(0.8f64);
-    if PERCENTAGE.contains(&percent) {
-        Ok(percent)
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OutputFormat {
+    Delimited,
+    Csv,
+    Tsv,
+    Jsonl,
+    Arrow,
+    Parquet
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum AlignmentFormat {
+    Fasta,
+    Phylip,
+    Nexus,
+    Clustal
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LdFormat {
+    Long,
+    Matrix
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ConsensusRule {
+    Majority,
+    Iupac
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum SubstitutionBase {
+    Reference,
+    Consensus
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogoAlphabet {
+    Nucleotide,
+    Protein
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum TimeBin {
+    Week,
+    Month
+}
+
+fn validate_genetic_code(input_str: &str) -> Result<u8, String> {
+    let tables = supported_tables();
+    match input_str.parse::<u8>() {
+        Ok(table) if tables.contains(&table) => Ok(table),
+        _ => Err(
+            format!("Translation table not in the supported tables {:?}", tables)
+        )
+    }
+}
+
+fn validate_region(input_str: &str) -> Result<(usize, usize), String> {
+    match input_str.split_once('-').map(|(x, y)| (x.trim().parse::<usize>(), y.trim().parse::<usize>())) {
+        Some((Ok(start), Ok(end))) if start > 0 && start <= end => Ok((start, end)),
+        _ => Err(String::from("Region must be START-END with 0 < START <= END"))
+    }
+}
+
+fn validate_output(input_str: &str) -> Result<String, String> {
+    if input_str == "-" || (input_str.contains("{metric}") && input_str.contains("{ext}")) {
+        Ok(input_str.to_string())
     } else {
-        Err(
-            format!("Threshold not in the range {} - {}",PERCENTAGE.start(), PERCENTAGE.end())
+        Err(String::from("Output must be \"-\" or a name template holding {metric} and {ext}"))
+    }
+}
+
+fn validate_positive(input_str: &str) -> Result<usize, String> {
+    match input_str.parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(String::from("Value must be a positive integer"))
+    }
+}
+
+fn validate_percent(input_str: &str) -> Result<f64, String> {
+    match input_str.parse::<f64>() {
+        Ok(percent) if PERCENTAGE.contains(&percent) => Ok(percent),
+        _ => Err(
+            format!("Fraction must be a number in the range {} - {}", PERCENTAGE.start(), PERCENTAGE.end())
         )
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_is_a_fraction() {
+        assert_eq!(validate_percent("0"), Ok(0.0));
+        assert_eq!(validate_percent("0.25"), Ok(0.25));
+        assert_eq!(validate_percent("1"), Ok(1.0));
+        ["", "abc", "0,8", "-0.1", "1.5", "NaN"].iter()
+            .for_each(|x| assert!(validate_percent(x).is_err(), "{x}"));
+    }
+}
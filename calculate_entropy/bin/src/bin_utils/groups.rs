@@ -0,0 +1,140 @@
+/*
+    Shannon entropy per metadata group
+        Read the metadata and find the group column
+        One pass over the alignment
+            match every sequence to its metadata row through the header
+            count the notations of the sequence in the columns of its group
+            sequences without a row or a group value are logged and counted
+        Write the number of sequences of every group, with the sequences without a group as NA
+        Write the entropy of every position for every group, groups in sorted order
+    Every group holds counts of the whole alignment length
+        memory grows as groups x alignment length, a column per lineage can be large
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use log::{info, trace, warn};
+use threadpool::ThreadPool;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{analyse_genomes, create_output, entropy_fields, entropy_headers, output_name};
+use super::get_args::EntropyArgs;
+use super::metadata::{read_metadata, sequence_id};
+
+type ColumnCounts = Vec<HashMap<char, f64>>;
+
+pub fn report_groups(map_vec: &[HashMap<char, f64>], file: &mut FileBufferHelper,
+                     cli: &EntropyArgs) -> Result<(), String> {
+    let (metadata_path, group_column) = match (&cli.metadata, &cli.group_by) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return Ok(())
+    };
+    let metadata = read_metadata(metadata_path, cli.metadata_id.as_ref())?;
+    let group_index = metadata.column_index(group_column)
+        .ok_or(format!("Group column {} not found in metadata {:?}", group_column, metadata_path))?;
+    let (groups, unmatched) = grouped_counts(file, map_vec, cli, |header| {
+        sequence_id(header, cli)
+            .and_then(|id| metadata.value(id, group_index))
+            .map(|x| x.to_string())
+    });
+    info!("{} groups of {}, {} sequences not matched to a group", groups.len(), group_column, unmatched);
+
+    let out_file_name = output_name(file, &cli.output, "group_summary");
+    info!("Group summary output file: {}", out_file_name);
+    let mut summary_writer = BufWriter::new(create_output(&out_file_name, &cli.output)?);
+    writeln!(summary_writer, "Group{delim}Sequences", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    groups.iter().for_each(|(group, (_, genome_count))| {
+        writeln!(summary_writer, "{group}{delim}{genome_count}", delim=cli.output.delimiter)
+            .expect("Unable to write to file");
+    });
+    writeln!(summary_writer, "NA{delim}{unmatched}", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+
+    let out_file_name = output_name(file, &cli.output, "groups");
+    info!("Group entropy output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut group_writer = BufWriter::new(out_file);
+    writeln!(group_writer, "Group{}Position{}", cli.output.delimiter, entropy_headers(cli))
+        .expect("Unable to write to file");
+    groups.iter().for_each(|(group, (counts, genome_count))| {
+        counts.iter().enumerate().for_each(|(idx, char_map)| {
+            writeln!(group_writer, "{group}{delim}{pos}{fields}", pos=idx + 1,
+                     fields=entropy_fields(char_map, *genome_count, cli), delim=cli.output.delimiter)
+                .expect("Unable to write to file");
+        });
+    });
+    Ok(())
+}
+
+// counts of every group and their genome counts, with the number of sequences without a group
+// group_of: group of a sequence from its header, None if the sequence has no group
+// a full set of column counts is kept for every group
+pub fn grouped_counts<F>(file: &mut FileBufferHelper, map_vec: &[HashMap<char, f64>], cli: &EntropyArgs,
+                         group_of: F) -> (BTreeMap<String, (ColumnCounts, f64)>, usize)
+where F: Fn(&str) -> Option<String> {
+    // empty counts with the notations of the alignment wide counts
+    let template: ColumnCounts = map_vec.iter()
+                                    .map(|char_map| char_map.keys().map(|x| (*x, 0.0f64)).collect())
+                                    .collect();
+    let mut groups: BTreeMap<String, (Arc<Mutex<ColumnCounts>>, f64)> = BTreeMap::new();
+    let mut unmatched = 0usize;
+    let pool = ThreadPool::new(cli.counting.nproc);
+    file.buffer_reset();
+    while let Some((header, genome)) = file.next_record() {
+        let group = match group_of(&header) {
+            Some(x) => x,
+            None => {
+                warn!("No group found for {}", header);
+                unmatched += 1;
+                continue
+            }
+        };
+        trace!("Processing {} in group {}", header, group);
+        let (counts, genome_count) = groups.entry(group)
+                                        .or_insert_with(|| (Arc::new(Mutex::new(template.clone())), 0.0));
+        *genome_count += 1.0;
+        let arc_clone = Arc::clone(counts);
+        pool.execute(move || {
+            analyse_genomes(genome, arc_clone, header, None);
+        });
+    }
+    pool.join();
+    file.buffer_reset();
+    let groups = groups.into_iter()
+                    .map(|(group, (counts, genome_count))| {
+                        let counts = Arc::try_unwrap(counts).unwrap().into_inner().unwrap();
+                        (group, (counts, genome_count))
+                    })
+                    .collect();
+    (groups, unmatched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    #[test]
+    fn sequences_without_a_group_are_na() {
+        let dir = TestDir::new("groups");
+        let records = [("s1", "ACGT"), ("s2", "ACGA"), ("s3", "TCGA"), ("s4", "ACGT"), ("s5", "ACGT")];
+        let path = dir.alignment("a.fasta", &records);
+        // s4 has no lineage, s5 no row
+        let metadata = dir.file("metadata.tsv", "id\tlineage\ns1\tB.1\ns2\tB.1\ns3\tP.1\ns4\tNA\n");
+        let sequences: Vec<&str> = records.iter().map(|(_, x)| *x).collect();
+        let (map_vec, _) = counts(&sequences, &Mode::Standard);
+        let cli = entropy_args(&path, &["--metadata", metadata.to_str().unwrap(), "--group-by", "lineage"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        report_groups(&map_vec, &mut file, &cli).unwrap();
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_group_summary"),
+                   "Group,Sequences\nB.1,2\nP.1,1\nNA,2\n");
+        let output = dir.read("a.fasta_shannon_entropy_output_groups");
+        let rows: Vec<Vec<&str>> = output.lines().skip(1).map(|x| x.split(',').collect()).collect();
+        assert_eq!(rows.iter().map(|x| (x[0], x[1])).collect::<Vec<(&str, &str)>>(),
+                   [("B.1", "1"), ("B.1", "2"), ("B.1", "3"), ("B.1", "4"),
+                    ("P.1", "1"), ("P.1", "2"), ("P.1", "3"), ("P.1", "4")]);
+    }
+}
@@ -0,0 +1,250 @@
+/*
+    Linkage disequilibrium between variable positions
+        Restrict to valid positions with Shannon entropy above the cutoff
+        Major and minor allele of every position from the column counts
+            sequences carrying neither are left out of the pairs of that position
+        For every pair of positions within the maximum distance
+            pA, pB: frequency of the major alleles
+            pAB: frequency of sequences carrying both major alleles
+            D = pAB - pA pB
+            D' = |D| / Dmax
+            r^2 = D^2 / (pA (1 - pA) pB (1 - pB))
+        Report as a long table, or as r^2 and D' matrices
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{mpsc, Arc};
+use threadpool::ThreadPool;
+use log::{info, warn};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::alleles::ranked_alleles;
+use super::calculate_entropy::{considered_notations, create_output, output_name, valid_entropies};
+use super::covariation::collect_columns;
+use super::get_args::{EntropyArgs, LdFormat};
+
+const MAJOR: u8 = 0;
+const MINOR: u8 = 1;
+const OTHER: u8 = 2;
+
+struct VariableSite {
+    position: usize,
+    major: char,
+    minor: char,
+}
+
+struct PairLinkage {
+    first: usize,
+    second: usize,
+    shared: usize,
+    // D, D' and r^2, None if either position is monomorphic among shared sequences
+    statistics: Option<(f64, f64, f64)>,
+}
+
+pub fn report_linkage(map_vec: &[HashMap<char, f64>], genome_count: f64,
+                      file: &mut FileBufferHelper, cli: &EntropyArgs) -> Result<(), String> {
+    let notations = considered_notations(&cli.counting.mode);
+    let entropies = valid_entropies(map_vec, genome_count, &cli.counting);
+    let sites: Vec<VariableSite> = entropies.iter().enumerate()
+        .filter(|(_, x)| matches!(x, Some(entropy) if *entropy > 0.0 &&
+                                                      *entropy >= cli.ld_entropy_cutoff))
+        .filter_map(|(idx, _)| {
+            match ranked_alleles(&map_vec[idx])[..] {
+                [(major, _), (minor, _), ..] => Some(VariableSite { position: idx, major, minor }),
+                _ => None
+            }
+        })
+        .collect();
+    info!("{} variable positions above the entropy cutoff of {} considered for linkage",
+        sites.len(), cli.ld_entropy_cutoff);
+    if sites.len() < 2 {
+        warn!("Less than 2 variable positions. Linkage disequilibrium not reported.");
+        return Ok(())
+    }
+    // recode the notation of every sequence as major, minor or other allele
+    let positions: Vec<usize> = sites.iter().map(|x| x.position).collect();
+    let haplotypes: Vec<Vec<u8>> = collect_columns(file, &positions, notations).iter()
+        .zip(&sites)
+        .map(|(column, site)| {
+            let major = notations.find(site.major).unwrap() as u8;
+            let minor = notations.find(site.minor).unwrap() as u8;
+            column.iter().map(|&x| match x {
+                x if x == major => MAJOR,
+                x if x == minor => MINOR,
+                _ => OTHER
+            }).collect()
+        })
+        .collect();
+    let pairs = pairwise_linkage(Arc::new(haplotypes), Arc::new(positions),
+                                 cli.ld_max_distance, cli.counting.nproc);
+    match cli.ld_format {
+        LdFormat::Long => write_long(&pairs, &sites, file, cli),
+        LdFormat::Matrix => {
+            write_matrix(&pairs, &sites, file, cli, "ld_r2_matrix", |x| x.2)?;
+            write_matrix(&pairs, &sites, file, cli, "ld_dprime_matrix", |x| x.1)
+        }
+    }
+}
+
+fn pairwise_linkage(haplotypes: Arc<Vec<Vec<u8>>>, positions: Arc<Vec<usize>>,
+                    max_distance: Option<usize>, nproc: usize) -> Vec<PairLinkage> {
+    // one job per position, pairing it with the following positions within max_distance
+    let pool = ThreadPool::new(nproc);
+    let (sender, receiver) = mpsc::channel();
+    for first in 0..haplotypes.len() {
+        let haplotypes_clone = Arc::clone(&haplotypes);
+        let positions_clone = Arc::clone(&positions);
+        let sender_clone = sender.clone();
+        pool.execute(move || {
+            let row: Vec<PairLinkage> = (first + 1..haplotypes_clone.len())
+                .take_while(|&second| max_distance.is_none_or(|x|
+                                positions_clone[second] - positions_clone[first] <= x))
+                .map(|second| {
+                    let (shared, statistics) = linkage(&haplotypes_clone[first],
+                                                       &haplotypes_clone[second]);
+                    PairLinkage { first, second, shared, statistics }
+                })
+                .collect();
+            sender_clone.send(row).expect("Unable to send linkage statistics");
+        });
+    }
+    drop(sender);
+    let mut pairs: Vec<PairLinkage> = receiver.iter().flatten().collect();
+    pool.join();
+    pairs.sort_by_key(|x| (x.first, x.second));
+    info!("Linkage disequilibrium calculated for {} pairs", pairs.len());
+    pairs
+}
+
+fn linkage(first: &[u8], second: &[u8]) -> (usize, Option<(f64, f64, f64)>) {
+    let mut shared = 0usize;
+    let mut major_first = 0usize;
+    let mut major_second = 0usize;
+    let mut major_both = 0usize;
+    first.iter().zip(second).for_each(|(&a, &b)| {
+        if a != OTHER && b != OTHER {
+            shared += 1;
+            major_first += (a == MAJOR) as usize;
+            major_second += (b == MAJOR) as usize;
+            major_both += (a == MAJOR && b == MAJOR) as usize;
+        }
+    });
+    if shared == 0 {
+        return (0, None)
+    }
+    let total = shared as f64;
+    let p_a = major_first as f64/total;
+    let p_b = major_second as f64/total;
+    let p_ab = major_both as f64/total;
+    let denominator = p_a * (1.0 - p_a) * p_b * (1.0 - p_b);
+    if denominator == 0.0 {
+        return (shared, None)
+    }
+    let d = p_ab - p_a * p_b;
+    let d_max = if d > 0.0 {
+        (p_a * (1.0 - p_b)).min((1.0 - p_a) * p_b)
+    } else {
+        (p_a * p_b).min((1.0 - p_a) * (1.0 - p_b))
+    };
+    let d_prime = if d_max > 0.0 { d.abs()/d_max } else { 0.0 };
+    (shared, Some((d, d_prime, d * d/denominator)))
+}
+
+fn create_writer(file: &FileBufferHelper, cli: &EntropyArgs, metric: &str) -> Result<BufWriter<File>, String> {
+    let out_file_name = output_name(file, &cli.output, metric);
+    info!("Linkage disequilibrium output file: {}", out_file_name);
+    Ok(BufWriter::new(create_output(&out_file_name, &cli.output)?))
+}
+
+fn write_long(pairs: &[PairLinkage], sites: &[VariableSite], file: &FileBufferHelper,
+              cli: &EntropyArgs) -> Result<(), String> {
+    let mut ld_writer = create_writer(file, cli, "ld")?;
+    writeln!(ld_writer, "Position_1{delim}Position_2{delim}Distance{delim}Major_1{delim}\
+                         Minor_1{delim}Major_2{delim}Minor_2{delim}Shared_sequences{delim}\
+                         D{delim}D_prime{delim}r2", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    pairs.iter().for_each(|pair| {
+        let (first, second) = (&sites[pair.first], &sites[pair.second]);
+        let statistics = match pair.statistics {
+            Some((d, d_prime, r2)) => format!("{d}{delim}{d_prime}{delim}{r2}", delim=cli.output.delimiter),
+            None => format!("NA{delim}NA{delim}NA", delim=cli.output.delimiter)
+        };
+        writeln!(ld_writer, "{pos_1}{delim}{pos_2}{delim}{distance}{delim}{major_1}{delim}\
+                             {minor_1}{delim}{major_2}{delim}{minor_2}{delim}{shared}{delim}\
+                             {statistics}",
+                 pos_1=first.position + 1, pos_2=second.position + 1,
+                 distance=second.position - first.position, major_1=first.major,
+                 minor_1=first.minor, major_2=second.major, minor_2=second.minor,
+                 shared=pair.shared, delim=cli.output.delimiter).expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+fn write_matrix(pairs: &[PairLinkage], sites: &[VariableSite], file: &FileBufferHelper, cli: &EntropyArgs,
+                metric: &str, statistic: fn(&(f64, f64, f64)) -> f64) -> Result<(), String> {
+    // symmetric matrix of positions, NA for pairs not calculated
+    let mut matrix: Vec<Vec<Option<f64>>> = vec![vec![None; sites.len()]; sites.len()];
+    (0..sites.len()).for_each(|idx| matrix[idx][idx] = Some(1.0));
+    pairs.iter().for_each(|pair| {
+        let value = pair.statistics.as_ref().map(statistic);
+        matrix[pair.first][pair.second] = value;
+        matrix[pair.second][pair.first] = value;
+    });
+    let mut matrix_writer = create_writer(file, cli, metric)?;
+    let positions = sites.iter()
+                        .map(|x| format!("{}{}", cli.output.delimiter, x.position + 1))
+                        .collect::<String>();
+    writeln!(matrix_writer, "Position{positions}").expect("Unable to write to file");
+    matrix.iter().zip(sites).for_each(|(row, site)| {
+        let values = row.iter()
+                        .map(|x| format!("{}{}", cli.output.delimiter,
+                                         x.map_or(String::from("NA"), |x| x.to_string())))
+                        .collect::<String>();
+        writeln!(matrix_writer, "{}{values}", site.position + 1).expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_linkage(first: &[u8], second: &[u8], expected: (usize, f64, f64, f64)) {
+        let (shared, values) = linkage(first, second);
+        let (d, d_prime, r_squared) = values.unwrap();
+        assert_eq!(shared, expected.0);
+        assert!((d - expected.1).abs() < 1e-12);
+        assert!((d_prime - expected.2).abs() < 1e-12);
+        assert!((r_squared - expected.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn complete_linkage() {
+        assert_linkage(&[MAJOR, MAJOR, MINOR, MINOR], &[MAJOR, MAJOR, MINOR, MINOR], (4, 0.25, 1.0, 1.0));
+        // repulsion: major allele of one position with the minor allele of the other
+        assert_linkage(&[MAJOR, MAJOR, MINOR, MINOR], &[MINOR, MINOR, MAJOR, MAJOR], (4, -0.25, 1.0, 1.0));
+    }
+
+    #[test]
+    fn incomplete_linkage() {
+        // pA = 0.75, pB = 0.5, pAB = 0.5: D = 0.125 = Dmax, r^2 = 0.125^2 / (0.75 0.25 0.5 0.5)
+        assert_linkage(&[MAJOR, MAJOR, MAJOR, MINOR], &[MAJOR, MAJOR, MINOR, MINOR], (4, 0.125, 1.0, 1.0/3.0));
+    }
+
+    #[test]
+    fn linkage_equilibrium() {
+        assert_linkage(&[MAJOR, MAJOR, MINOR, MINOR], &[MAJOR, MINOR, MAJOR, MINOR], (4, 0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn other_alleles_are_left_out() {
+        assert_linkage(&[MAJOR, OTHER, MINOR, MAJOR], &[MAJOR, MAJOR, MINOR, OTHER], (2, 0.25, 1.0, 1.0));
+        assert_eq!(linkage(&[OTHER], &[MAJOR]), (0, None));
+    }
+
+    #[test]
+    fn monomorphic_position_has_no_linkage() {
+        assert_eq!(linkage(&[MAJOR, MAJOR], &[MAJOR, MINOR]), (2, None));
+    }
+}
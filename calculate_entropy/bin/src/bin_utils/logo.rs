@@ -0,0 +1,233 @@
+/*
+    SVG sequence logo of a region of the alignment
+        Nucleotide logo: one stack per alignment column, letters A, C, G and T
+        Protein logo: one stack per codon with a base in the region
+            amino acid counts of the codon, from CDS features or the reading frame
+        Per stack, over the observed letters (gaps, ambiguity codes and stops left out)
+            information content = log2(alphabet size) - (entropy + small sample correction)
+            small sample correction (optional) = (alphabet size - 1)/(2 ln 2 n), n observed letters
+            letter height = frequency x information content, most frequent letter on top
+        Colour schemes
+            nucleotide: A green, C blue, G orange, T red
+            protein: by chemistry, polar, neutral, basic, acidic and hydrophobic
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use log::{info, warn};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::annotation::Annotation;
+use super::calculate_entropy::{create_output, get_entropy, notation_counts, output_name};
+use super::codon::{codon_sites, count_codons};
+use super::genetic_code::GeneticCode;
+use super::get_args::{EntropyArgs, LogoAlphabet};
+use super::plot::escape_markup;
+use super::reference::Reference;
+
+const NUCLEOTIDES: &str = "ACGT";
+const AMINO_ACIDS: &str = "ACDEFGHIKLMNPQRSTVWY";
+const STACK_WIDTH: f64 = 30.0;
+const LOGO_HEIGHT: f64 = 200.0;
+const MARGIN_LEFT: f64 = 60.0;
+const MARGIN_TOP: f64 = 40.0;
+const LABEL_SPACE: f64 = 70.0;
+const FONT_SIZE: f64 = 100.0;
+// height of a capital letter relative to the font size
+const CAP_HEIGHT: f64 = 0.72;
+
+pub fn report_logo(map_vec: &[HashMap<char, f64>], file: &mut FileBufferHelper, cli: &EntropyArgs,
+                   reference: Option<&Reference>, annotation: Option<&Annotation>) -> Result<(), String> {
+    let (start, end) = match cli.logo {
+        Some(x) => x,
+        None => return Ok(())
+    };
+    if end > map_vec.len() {
+        return Err(format!("Logo region {}-{} is beyond the {} positions of {:?}",
+                           start, end, map_vec.len(), file.path))
+    }
+    // label and letter counts of every stack
+    let stacks: Vec<(String, Vec<(char, f64)>)> = match (cli.logo_alphabet, reference) {
+        (LogoAlphabet::Nucleotide, _) => (start - 1..end).map(|idx| {
+            let counts = NUCLEOTIDES.chars().zip(notation_counts(&map_vec[idx], NUCLEOTIDES)).collect();
+            ((idx + 1).to_string(), counts)
+        }).collect(),
+        (LogoAlphabet::Protein, Some(reference)) => {
+            let sites: Vec<_> = codon_sites(reference, annotation, cli.frame as usize).into_iter()
+                .filter(|site| site.columns.iter().any(|x| (start - 1..end).contains(x)))
+                .collect();
+            let labels: Vec<String> = sites.iter()
+                                        .map(|x| format!("{}:{}", x.feature, x.codon_number))
+                                        .collect();
+            let genetic_code = GeneticCode::new(cli.genetic_code)
+                                                .expect("Translation table validated by the parser");
+            let codon_counts = count_codons(file, cli, Arc::new(sites), genetic_code);
+            labels.into_iter().zip(codon_counts).map(|(label, counts)| {
+                let counts = AMINO_ACIDS.chars()
+                                .map(|x| (x, counts.amino_acids.get(&x).copied().unwrap_or(0.0)))
+                                .collect();
+                (label, counts)
+            }).collect()
+        },
+        (LogoAlphabet::Protein, None) => return Err(String::from("A protein logo needs the reference sequence"))
+    };
+    if stacks.is_empty() {
+        warn!("No codons in the logo region {}-{}", start, end);
+        return Ok(())
+    }
+    let alphabet_size = match cli.logo_alphabet {
+        LogoAlphabet::Nucleotide => NUCLEOTIDES.len(),
+        LogoAlphabet::Protein => AMINO_ACIDS.len()
+    } as f64;
+    let title = format!("{} {}-{}", file.path.file_stem().map_or(String::from("alignment"),
+                                                                 |x| x.to_string_lossy().to_string()),
+                        start, end);
+    let svg = logo_svg(&stacks, alphabet_size, &title, cli);
+    let out_file_name = output_name(file, &cli.output, "logo.svg");
+    info!("Sequence logo output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut logo_writer = BufWriter::new(out_file);
+    write!(logo_writer, "{}", svg).expect("Unable to write to file");
+    Ok(())
+}
+
+fn letter_colour(letter: char, alphabet: LogoAlphabet) -> &'static str {
+    match alphabet {
+        LogoAlphabet::Nucleotide => match letter {
+            'A' => "#109648",
+            'C' => "#255c99",
+            'G' => "#f7b32b",
+            _ => "#d62839"
+        },
+        LogoAlphabet::Protein => match letter {
+            'G' | 'S' | 'T' | 'Y' | 'C' => "#109648",
+            'Q' | 'N' => "#8e44ad",
+            'K' | 'R' | 'H' => "#255c99",
+            'D' | 'E' => "#d62839",
+            _ => "#000000"
+        }
+    }
+}
+
+// heights in bits of the letters of a stack, from the bottom to the top
+fn stack_heights(counts: &[(char, f64)], alphabet_size: f64, correction: bool) -> Vec<(char, f64)> {
+    let observed: f64 = counts.iter().map(|(_, count)| count).sum();
+    if observed == 0.0 {
+        return Vec::new()
+    }
+    let letter_counts: Vec<f64> = counts.iter().map(|(_, count)| *count).collect();
+    let small_sample = if correction {
+        (alphabet_size - 1.0)/(2.0 * std::f64::consts::LN_2 * observed)
+    } else {
+        0.0
+    };
+    let information = (alphabet_size.log2() - (get_entropy(&letter_counts) + small_sample)).max(0.0);
+    let mut heights: Vec<(char, f64)> = counts.iter()
+                                            .filter(|(_, count)| *count > 0.0)
+                                            .map(|(x, count)| (*x, count/observed * information))
+                                            .collect();
+    heights.sort_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)));
+    heights
+}
+
+fn logo_svg(stacks: &[(String, Vec<(char, f64)>)], alphabet_size: f64, title: &str, cli: &EntropyArgs) -> String {
+    let maximum_information = alphabet_size.log2();
+    let width = MARGIN_LEFT + stacks.len() as f64 * STACK_WIDTH + 20.0;
+    let height = MARGIN_TOP + LOGO_HEIGHT + LABEL_SPACE;
+    let bits_to_pixels = LOGO_HEIGHT/maximum_information;
+    let bottom = MARGIN_TOP + LOGO_HEIGHT;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+                           viewBox=\"0 0 {width} {height}\" font-family=\"Arial, Helvetica, sans-serif\" \
+                           font-size=\"12\">\n");
+    svg += &format!("<title>Sequence logo of {}</title>\n", escape_markup(title));
+    svg += &format!("<rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>\n");
+    svg += &format!("<text x=\"{MARGIN_LEFT}\" y=\"20\" font-size=\"14\">Sequence logo of {}</text>\n",
+                    escape_markup(title));
+    // y axis in bits
+    svg += &format!("<path d=\"M{MARGIN_LEFT} {MARGIN_TOP}V{bottom}H{right}\" stroke=\"black\" fill=\"none\"/>\n",
+                    right=width - 20.0);
+    (0..=maximum_information.floor() as usize).for_each(|bits| {
+        let y = bottom - bits as f64 * bits_to_pixels;
+        svg += &format!("<path d=\"M{MARGIN_LEFT} {y:.2}h-5\" stroke=\"black\"/>\
+                         <text x=\"{x}\" y=\"{y:.2}\" text-anchor=\"end\" dominant-baseline=\"middle\">{bits}</text>\n",
+                        x=MARGIN_LEFT - 8.0);
+    });
+    svg += &format!("<text transform=\"translate(18 {}) rotate(-90)\" text-anchor=\"middle\">Bits</text>\n",
+                    MARGIN_TOP + LOGO_HEIGHT/2.0);
+    stacks.iter().enumerate().for_each(|(idx, (label, counts))| {
+        let x = MARGIN_LEFT + idx as f64 * STACK_WIDTH;
+        let mut y = bottom;
+        stack_heights(counts, alphabet_size, cli.small_sample_correction).iter()
+            .for_each(|(letter, bits)| {
+                let letter_height = bits * bits_to_pixels;
+                if letter_height >= 0.5 {
+                    svg += &format!("<text transform=\"translate({lx:.2} {y:.2}) scale(1 {scale:.4})\" \
+                                     font-size=\"{FONT_SIZE}\" font-weight=\"bold\" textLength=\"{w}\" \
+                                     lengthAdjust=\"spacingAndGlyphs\" fill=\"{fill}\">{letter}</text>\n",
+                                    lx=x + 1.0, scale=letter_height/(FONT_SIZE * CAP_HEIGHT), w=STACK_WIDTH - 2.0,
+                                    fill=letter_colour(*letter, cli.logo_alphabet));
+                }
+                y -= letter_height;
+            });
+        svg += &format!("<text transform=\"translate({tx:.2} {ty}) rotate(-90)\" text-anchor=\"end\" \
+                         dominant-baseline=\"middle\" font-size=\"10\">{label}</text>\n",
+                        tx=x + STACK_WIDTH/2.0, ty=bottom + 6.0, label=escape_markup(label));
+    });
+    svg += "</svg>\n";
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    #[test]
+    fn letter_heights_of_fixed_columns() {
+        let counts = |a, c| [('A', a), ('C', c), ('G', 0.0), ('T', 0.0)];
+        assert_eq!(stack_heights(&counts(4.0, 0.0), 4.0, false), [('A', 2.0)]);
+        // ties are drawn in alphabetical order from the top
+        assert_eq!(stack_heights(&counts(2.0, 2.0), 4.0, false), [('C', 0.5), ('A', 0.5)]);
+        assert_eq!(stack_heights(&counts(1.0, 3.0), 4.0, false)[1].0, 'C');
+        assert!(stack_heights(&counts(0.0, 0.0), 4.0, false).is_empty());
+        // (4 - 1)/(2 ln2 4) bits are taken off a column of 4 letters
+        let corrected = stack_heights(&counts(4.0, 0.0), 4.0, true);
+        assert!((corrected[0].1 - (2.0 - 3.0/(8.0 * std::f64::consts::LN_2))).abs() < 1e-12);
+    }
+
+    #[test]
+    fn logo_of_a_fixed_column() {
+        let dir = TestDir::new("logo");
+        let path = dir.alignment("a.fasta", &[("s1", "AAG"), ("s2", "ACG"), ("s3", "AAG"), ("s4", "ACG")]);
+        let (map_vec, _) = counts(&["AAG", "ACG", "AAG", "ACG"], &Mode::Standard);
+        let cli = entropy_args(&path, &["--logo", "1-2"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        report_logo(&map_vec, &mut file, &cli, None, None).unwrap();
+        let svg = dir.read("a.fasta_shannon_entropy_output_logo.svg");
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"140\" height=\"310\""));
+        assert!(svg.contains("<title>Sequence logo of a 1-2</title>"));
+        // one letter of 2 bits in the first stack, two letters of 0.5 bits in the second
+        assert_eq!(svg.matches("font-weight=\"bold\"").count(), 3);
+        assert!(svg.contains("translate(61.00 240.00) scale(1 2.7778)"));
+        assert!(svg.contains("translate(91.00 240.00) scale(1 0.6944)"));
+        assert!(svg.contains("translate(91.00 190.00) scale(1 0.6944)"));
+        // axis ticks of 0, 1 and 2 bits
+        assert_eq!(svg.matches("h-5\"").count(), 3);
+    }
+
+    #[test]
+    fn invalid_logo_requests() {
+        let dir = TestDir::new("logo_errors");
+        let path = dir.alignment("a.fasta", &[("s1", "AAG"), ("s2", "ACG")]);
+        let (map_vec, _) = counts(&["AAG", "ACG"], &Mode::Standard);
+        let mut file = check_fasta(&path, true).unwrap();
+        let cli = entropy_args(&path, &["--logo", "2-4"]);
+        let error = report_logo(&map_vec, &mut file, &cli, None, None).unwrap_err();
+        assert!(error.contains("beyond the 3 positions"), "{error}");
+        let cli = entropy_args(&path, &["--logo", "1-3", "--logo-alphabet", "protein"]);
+        assert!(report_logo(&map_vec, &mut file, &cli, None, None).is_err());
+        assert!(dir.files().iter().all(|x| !x.ends_with("logo.svg")));
+    }
+}
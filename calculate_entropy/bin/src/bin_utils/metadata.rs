@@ -0,0 +1,115 @@
+/*
+    Sequence metadata from a tab separated file
+        First line holds the column names
+        One row per sequence, identified by the ID column (default: first column)
+    Match sequences to rows
+        split the fasta header on the ID separator (default: "|")
+        take the ID field (1 based, default: 1)
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use log::{info, warn};
+use super::get_args::EntropyArgs;
+
+pub struct Metadata {
+    pub columns: Vec<String>,
+    rows: HashMap<String, Vec<String>>,
+}
+
+impl Metadata {
+    pub fn column_index(&self, column: &str) -> Option<usize> {
+        self.columns.iter().position(|x| x == column)
+    }
+
+    pub fn value(&self, id: &str, column_index: usize) -> Option<&str> {
+        // missing, empty and NA values are treated alike
+        self.rows.get(id)
+            .and_then(|row| row.get(column_index))
+            .map(|x| x.as_str())
+            .filter(|x| !x.is_empty() && *x != "NA")
+    }
+}
+
+pub fn read_metadata(path: &PathBuf, id_column: Option<&String>) -> Result<Metadata, String> {
+    let metadata_file = File::open(path)
+        .map_err(|e| format!("Unable to open metadata {:?} - {}", path, e))?;
+    let mut lines = BufReader::new(metadata_file).lines();
+    let columns: Vec<String> = match lines.next() {
+        Some(Ok(line)) => line.trim_end_matches(['\r', '\n'])
+                                .split('\t')
+                                .map(|x| x.trim().to_string())
+                                .collect(),
+        _ => return Err(format!("Metadata {:?} has no header line", path))
+    };
+    let id_index = match id_column {
+        Some(id_column) => columns.iter().position(|x| x == id_column)
+            .ok_or(format!("ID column {} not found in metadata {:?}", id_column, path))?,
+        None => 0
+    };
+    let mut rows: HashMap<String, Vec<String>> = HashMap::new();
+    for line in lines {
+        let line = line.map_err(|e| format!("Unable to read metadata {:?} - {}", path, e))?;
+        if line.trim().is_empty() {
+            continue
+        }
+        let row: Vec<String> = line.trim_end_matches(['\r', '\n'])
+                                .split('\t')
+                                .map(|x| x.trim().to_string())
+                                .collect();
+        let id = match row.get(id_index) {
+            Some(x) if !x.is_empty() => x.clone(),
+            _ => continue
+        };
+        if rows.insert(id.clone(), row).is_some() {
+            warn!("Metadata ID {} is repeated, the last row is kept", id);
+        }
+    }
+    info!("Metadata of {} sequences read from {:?}", rows.len(), path);
+    Ok(Metadata { columns, rows })
+}
+
+// sequence ID used to look up the metadata, from the fasta header without ">"
+pub fn sequence_id<'a>(header: &'a str, cli: &EntropyArgs) -> Option<&'a str> {
+    header.split(cli.id_separator)
+        .nth(cli.id_field - 1)
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_utils::testing::{entropy_args, TestDir};
+
+    #[test]
+    fn sequences_are_matched_through_the_id_field() {
+        let dir = TestDir::new("metadata");
+        let path = dir.alignment("a.fasta", &[("s1", "ACGT")]);
+        let metadata_path = dir.file("metadata.tsv", "lineage\tid\tdate\r\nB.1\tEPI_1\t2021-01-04\r\n\
+                                                      P.1\tEPI_2\tNA\r\n\r\nB.1\tEPI_3\t\r\n");
+        let metadata = read_metadata(&metadata_path, Some(&String::from("id"))).unwrap();
+        assert_eq!(metadata.columns, ["lineage", "id", "date"]);
+        let date = metadata.column_index("date").unwrap();
+        let cli = entropy_args(&path, &["--id-field", "2"]);
+        let value = |header| sequence_id(header, &cli).and_then(|id| metadata.value(id, date));
+        assert_eq!(value("hCoV-19/England/1/2021|EPI_1|2021-01"), Some("2021-01-04"));
+        // NA and empty values are missing, as are IDs without a row
+        assert_eq!(value("hCoV-19/England/2/2021|EPI_2"), None);
+        assert_eq!(value("hCoV-19/England/3/2021|EPI_3"), None);
+        assert_eq!(value("hCoV-19/England/4/2021|EPI_4"), None);
+        assert_eq!(value("hCoV-19/England/1/2021"), None);
+        assert_eq!(metadata.value("EPI_2", 0), Some("P.1"));
+    }
+
+    #[test]
+    fn missing_id_column_is_an_error() {
+        let dir = TestDir::new("metadata_id");
+        let metadata_path = dir.file("metadata.tsv", "strain\tlineage\ns1\tB.1\n");
+        assert!(read_metadata(&metadata_path, Some(&String::from("id"))).is_err());
+        let metadata = read_metadata(&metadata_path, None).unwrap();
+        assert_eq!(metadata.value("s1", 1), Some("B.1"));
+    }
+}
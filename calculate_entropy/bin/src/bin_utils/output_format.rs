@@ -0,0 +1,337 @@
+/*
+    Write a table of typed columns in the requested output format
+        the 'Delimited' format is not a table format, finalise_counts writes it as before
+        CSV / TSV
+            header line, booleans as true/false
+            missing values as empty fields, NaN as "NaN"
+            floats rounded to --precision decimals when given
+            fields holding the separator, quotes or newlines are quoted
+        JSON Lines
+            one object per row, keys are the column names
+            missing values, NaN and infinite values as null
+            floats rounded to --precision decimals when given
+        Arrow IPC file / Parquet
+            typed columns at full precision, missing values and NaN as null
+*/
+
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema};
+use arrow_ipc::writer::FileWriter;
+use parquet::arrow::ArrowWriter;
+use super::calculate_entropy::open_output;
+use super::get_args::{OutputFormat, OutputOptions};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    Float,
+    Text,
+    Boolean,
+}
+
+pub enum Value {
+    Integer(Option<u64>),
+    Float(Option<f64>),
+    Text(Option<String>),
+    Boolean(Option<bool>),
+}
+
+pub struct Table {
+    pub columns: Vec<(String, ColumnType)>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum TableFormat {
+    Csv,
+    Tsv,
+    Jsonl,
+    Arrow,
+    Parquet,
+}
+
+// format of a table of typed columns, None for the delimited output
+pub fn table_format(output_format: OutputFormat) -> Option<TableFormat> {
+    match output_format {
+        OutputFormat::Delimited => None,
+        OutputFormat::Csv => Some(TableFormat::Csv),
+        OutputFormat::Tsv => Some(TableFormat::Tsv),
+        OutputFormat::Jsonl => Some(TableFormat::Jsonl),
+        OutputFormat::Arrow => Some(TableFormat::Arrow),
+        OutputFormat::Parquet => Some(TableFormat::Parquet),
+    }
+}
+
+// extension of the output file, None for the delimited output named without an extension
+pub fn format_extension(output_format: OutputFormat) -> Option<&'static str> {
+    table_format(output_format).map(|x| match x {
+        TableFormat::Csv => "csv",
+        TableFormat::Tsv => "tsv",
+        TableFormat::Jsonl => "jsonl",
+        TableFormat::Arrow => "arrow",
+        TableFormat::Parquet => "parquet",
+    })
+}
+
+pub fn write_table(table: &Table, out_file_name: &str, format: TableFormat, output: &OutputOptions,
+                   precision: Option<usize>) -> Result<(), String> {
+    // "-" writes the table to stdout
+    let out_file = open_output(out_file_name, output)?;
+    match format {
+        TableFormat::Csv => write_delimited(table, out_file, ',', precision),
+        TableFormat::Tsv => write_delimited(table, out_file, '\t', precision),
+        TableFormat::Jsonl => write_json_lines(table, out_file, precision),
+        TableFormat::Arrow => {
+            let batch = record_batch(table)?;
+            let mut writer = FileWriter::try_new(out_file, &batch.schema())
+                .map_err(|e| format!("Unable to write {} - {}", out_file_name, e))?;
+            writer.write(&batch).and_then(|_| writer.finish())
+                .map_err(|e| format!("Unable to write {} - {}", out_file_name, e))
+        },
+        TableFormat::Parquet => {
+            let batch = record_batch(table)?;
+            let mut writer = ArrowWriter::try_new(out_file, batch.schema(), None)
+                .map_err(|e| format!("Unable to write {} - {}", out_file_name, e))?;
+            writer.write(&batch)
+                .map_err(|e| format!("Unable to write {} - {}", out_file_name, e))?;
+            writer.close()
+                .map(|_| ())
+                .map_err(|e| format!("Unable to write {} - {}", out_file_name, e))
+        }
+    }
+}
+
+fn format_float(value: f64, precision: Option<usize>) -> String {
+    match precision {
+        Some(digits) => format!("{value:.digits$}"),
+        None => value.to_string()
+    }
+}
+
+fn write_delimited(table: &Table, out_file: Box<dyn Write + Send>, separator: char,
+                   precision: Option<usize>) -> Result<(), String> {
+    let mut table_writer = BufWriter::new(out_file);
+    let quote = |field: String| {
+        if field.contains(separator) || field.contains('"') || field.contains('\n') {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field
+        }
+    };
+    let headers = table.columns.iter()
+                    .map(|(name, _)| quote(name.clone()))
+                    .collect::<Vec<String>>()
+                    .join(&separator.to_string());
+    writeln!(table_writer, "{}", headers).map_err(|e| e.to_string())?;
+    for row in &table.rows {
+        let fields = row.iter()
+            .map(|value| match value {
+                Value::Integer(x) => x.map_or(String::new(), |x| x.to_string()),
+                Value::Float(x) => x.map_or(String::new(), |x| if x.is_nan() {
+                    String::from("NaN")
+                } else {
+                    format_float(x, precision)
+                }),
+                Value::Text(x) => x.clone().map_or(String::new(), quote),
+                Value::Boolean(x) => x.map_or(String::new(), |x| x.to_string())
+            })
+            .collect::<Vec<String>>()
+            .join(&separator.to_string());
+        writeln!(table_writer, "{}", fields).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    text.chars().for_each(|x| match x {
+        '"' => escaped += "\\\"",
+        '\\' => escaped += "\\\\",
+        '\n' => escaped += "\\n",
+        '\r' => escaped += "\\r",
+        '\t' => escaped += "\\t",
+        x if (x as u32) < 0x20 => escaped += &format!("\\u{:04x}", x as u32),
+        x => escaped.push(x)
+    });
+    escaped.push('"');
+    escaped
+}
+
+fn write_json_lines(table: &Table, out_file: Box<dyn Write + Send>, precision: Option<usize>) -> Result<(), String> {
+    let mut table_writer = BufWriter::new(out_file);
+    let keys: Vec<String> = table.columns.iter().map(|(name, _)| json_string(name)).collect();
+    for row in &table.rows {
+        let members = keys.iter().zip(row)
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::Integer(x) => x.map_or(String::from("null"), |x| x.to_string()),
+                    Value::Float(x) => x.filter(|x| x.is_finite())
+                                        .map_or(String::from("null"), |x| format_float(x, precision)),
+                    Value::Text(x) => x.as_ref().map_or(String::from("null"), |x| json_string(x)),
+                    Value::Boolean(x) => x.map_or(String::from("null"), |x| x.to_string())
+                };
+                format!("{key}:{value}")
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        writeln!(table_writer, "{{{}}}", members).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn record_batch(table: &Table) -> Result<RecordBatch, String> {
+    let fields: Vec<Field> = table.columns.iter()
+        .map(|(name, column_type)| {
+            let data_type = match column_type {
+                ColumnType::Integer => DataType::UInt64,
+                ColumnType::Float => DataType::Float64,
+                ColumnType::Text => DataType::Utf8,
+                ColumnType::Boolean => DataType::Boolean
+            };
+            Field::new(name, data_type, true)
+        })
+        .collect();
+    let arrays: Vec<ArrayRef> = table.columns.iter().enumerate()
+        .map(|(idx, (_, column_type))| {
+            let column = table.rows.iter().map(|row| &row[idx]);
+            let array: ArrayRef = match column_type {
+                ColumnType::Integer => Arc::new(column.map(|x| match x {
+                    Value::Integer(x) => *x,
+                    _ => None
+                }).collect::<UInt64Array>()),
+                ColumnType::Float => Arc::new(column.map(|x| match x {
+                    Value::Float(x) => x.filter(|x| !x.is_nan()),
+                    _ => None
+                }).collect::<Float64Array>()),
+                ColumnType::Text => Arc::new(column.map(|x| match x {
+                    Value::Text(x) => x.clone(),
+                    _ => None
+                }).collect::<StringArray>()),
+                ColumnType::Boolean => Arc::new(column.map(|x| match x {
+                    Value::Boolean(x) => *x,
+                    _ => None
+                }).collect::<BooleanArray>())
+            };
+            array
+        })
+        .collect();
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use arrow_array::Array;
+    use arrow_ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use crate::bin_utils::testing::TestDir;
+
+    fn output() -> OutputOptions {
+        OutputOptions {
+            output_suffix: String::from("shannon_entropy_output"),
+            delimiter: ',',
+            outdir: None,
+            name_template: None,
+            provenance: false,
+            no_clobber: false,
+        }
+    }
+
+    fn table() -> Table {
+        Table {
+            columns: vec![(String::from("Position"), ColumnType::Integer),
+                          (String::from("Feature"), ColumnType::Text),
+                          (String::from("Shannon_entropy"), ColumnType::Float),
+                          (String::from("Valid"), ColumnType::Boolean)],
+            rows: vec![vec![Value::Integer(Some(1)), Value::Text(Some(String::from("S \"spike\""))),
+                            Value::Float(Some(0.5)), Value::Boolean(Some(true))],
+                       vec![Value::Integer(Some(2)), Value::Text(None),
+                            Value::Float(Some(f64::NAN)), Value::Boolean(Some(false))],
+                       vec![Value::Integer(None), Value::Text(Some(String::from("a,b"))),
+                            Value::Float(None), Value::Boolean(None)]],
+        }
+    }
+
+    #[test]
+    fn delimited_output_is_not_a_table_format() {
+        assert!(table_format(OutputFormat::Delimited).is_none());
+        assert_eq!(format_extension(OutputFormat::Delimited), None);
+        assert!(table_format(OutputFormat::Parquet) == Some(TableFormat::Parquet));
+        assert_eq!(format_extension(OutputFormat::Tsv), Some("tsv"));
+    }
+
+    #[test]
+    fn csv_quotes_fields_and_keeps_nan() {
+        let dir = TestDir::new("table_csv");
+        let path = dir.path("table.csv");
+        write_table(&table(), path.to_str().unwrap(), TableFormat::Csv, &output(), Some(2)).unwrap();
+        assert_eq!(dir.read("table.csv"), "Position,Feature,Shannon_entropy,Valid\n\
+                                           1,\"S \"\"spike\"\"\",0.50,true\n\
+                                           2,,NaN,false\n\
+                                           ,\"a,b\",,\n");
+    }
+
+    #[test]
+    fn json_lines_round_trip() {
+        let dir = TestDir::new("table_jsonl");
+        let path = dir.path("table.jsonl");
+        write_table(&table(), path.to_str().unwrap(), TableFormat::Jsonl, &output(), None).unwrap();
+        let rows: Vec<serde_yaml::Mapping> = dir.read("table.jsonl").lines()
+                                                .map(|x| serde_yaml::from_str(x).unwrap())
+                                                .collect();
+        assert_eq!(rows.len(), 3);
+        let keys: Vec<&str> = rows[0].keys().map(|x| x.as_str().unwrap()).collect();
+        assert_eq!(keys, ["Position", "Feature", "Shannon_entropy", "Valid"]);
+        assert_eq!(rows[0]["Position"].as_u64(), Some(1));
+        assert_eq!(rows[0]["Feature"].as_str(), Some("S \"spike\""));
+        assert_eq!(rows[0]["Shannon_entropy"].as_f64(), Some(0.5));
+        assert_eq!(rows[0]["Valid"].as_bool(), Some(true));
+        // NaN and missing values are null
+        assert!(rows[1]["Feature"].is_null());
+        assert!(rows[1]["Shannon_entropy"].is_null());
+        assert!(rows[2]["Position"].is_null());
+        assert!(rows[2]["Valid"].is_null());
+    }
+
+    fn check_batch(batch: &RecordBatch) {
+        let schema = batch.schema();
+        let types: Vec<(&str, &DataType)> = schema.fields().iter()
+                                                .map(|x| (x.name().as_str(), x.data_type()))
+                                                .collect();
+        assert_eq!(types, [("Position", &DataType::UInt64), ("Feature", &DataType::Utf8),
+                           ("Shannon_entropy", &DataType::Float64), ("Valid", &DataType::Boolean)]);
+        let entropy = batch.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(entropy.value(0), 0.5);
+        // NaN is null
+        assert!(entropy.is_null(1));
+        let valid = batch.column(3).as_any().downcast_ref::<BooleanArray>().unwrap();
+        assert!(valid.value(0));
+        assert!(!valid.value(1));
+        assert!(valid.is_null(2));
+        assert!(batch.column(0).is_null(2));
+    }
+
+    #[test]
+    fn arrow_schema_round_trip() {
+        let dir = TestDir::new("table_arrow");
+        let path = dir.path("table.arrow");
+        write_table(&table(), path.to_str().unwrap(), TableFormat::Arrow, &output(), Some(1)).unwrap();
+        let mut reader = FileReader::try_new(File::open(&path).unwrap(), None).unwrap();
+        check_batch(&reader.next().unwrap().unwrap());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn parquet_schema_round_trip() {
+        let dir = TestDir::new("table_parquet");
+        let path = dir.path("table.parquet");
+        write_table(&table(), path.to_str().unwrap(), TableFormat::Parquet, &output(), None).unwrap();
+        let mut reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap()
+                            .build().unwrap();
+        check_batch(&reader.next().unwrap().unwrap());
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,267 @@
+/*
+    SVG plot of the entropy profile along the alignment
+        Self contained file, no scripts, fonts or links to other resources
+        x: alignment position, y: Shannon entropy up to the maximum entropy of the mode
+        Invalid positions (below the threshold) are shaded and break the profile
+        Optional
+            sliding window mean of the valid entropies (--window-size), centred on the position
+            horizontal line at the plot threshold
+            gene track of the CDS features of the annotation, overlapping features on separate rows
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::annotation::Annotation;
+use super::calculate_entropy::{considered_notations, create_output, output_name, valid_entropies};
+use super::get_args::EntropyArgs;
+use super::reference::Reference;
+
+const WIDTH: f64 = 1200.0;
+const PLOT_HEIGHT: f64 = 300.0;
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 40.0;
+const AXIS_SPACE: f64 = 45.0;
+const TRACK_ROW_HEIGHT: f64 = 20.0;
+
+pub fn report_plot(map_vec: &[HashMap<char, f64>], genome_count: f64, file: &FileBufferHelper, cli: &EntropyArgs,
+                   reference: Option<&Reference>, annotation: Option<&Annotation>) -> Result<(), String> {
+    let svg = profile_svg(map_vec, genome_count, file, cli, reference, annotation);
+    let out_file_name = output_name(file, &cli.output, "entropy_profile.svg");
+    info!("Entropy profile output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut plot_writer = BufWriter::new(out_file);
+    write!(plot_writer, "{}", svg).expect("Unable to write to file");
+    Ok(())
+}
+
+// entropy profile of the alignment, with the gene track when annotated
+pub fn profile_svg(map_vec: &[HashMap<char, f64>], genome_count: f64, file: &FileBufferHelper, cli: &EntropyArgs,
+                   reference: Option<&Reference>, annotation: Option<&Annotation>) -> String {
+    let entropies = valid_entropies(map_vec, genome_count, &cli.counting);
+    let stem = file.path.file_stem().map_or(String::from("alignment"),
+                                            |x| x.to_string_lossy().to_string());
+    let genes = match (reference, annotation) {
+        (Some(reference), Some(annotation)) => gene_rows(reference, annotation),
+        _ => Vec::new()
+    };
+    entropy_svg(&entropies, &stem, cli, &genes)
+}
+
+// SVG document of the entropy profile
+// genes: name, strand, first and last 0-based alignment column and track row
+fn entropy_svg(entropies: &[Option<f64>], title: &str, cli: &EntropyArgs,
+                   genes: &[(String, char, usize, usize, usize)]) -> String {
+    let length = entropies.len().max(1);
+    let maximum_entropy = (considered_notations(&cli.counting.mode).len() as f64).log2();
+    let track_rows = genes.iter().map(|x| x.4 + 1).max().unwrap_or(0);
+    let track_top = MARGIN_TOP + PLOT_HEIGHT + AXIS_SPACE;
+    let height = track_top + track_rows as f64 * TRACK_ROW_HEIGHT + 20.0;
+    let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let x_of = |position: f64| MARGIN_LEFT + (position/length as f64) * plot_width;
+    let y_of = |entropy: f64| MARGIN_TOP + PLOT_HEIGHT * (1.0 - entropy/maximum_entropy);
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{height}\" \
+                           viewBox=\"0 0 {WIDTH} {height}\" font-family=\"sans-serif\" font-size=\"12\">\n");
+    svg += &format!("<title>Shannon entropy of {}</title>\n", escape_markup(title));
+    svg += &format!("<rect width=\"{WIDTH}\" height=\"{height}\" fill=\"white\"/>\n");
+    svg += &format!("<text x=\"{MARGIN_LEFT}\" y=\"20\" font-size=\"14\">Shannon entropy of {}</text>\n",
+                    escape_markup(title));
+
+    // invalid positions, merged into runs
+    svg += "<g fill=\"#dddddd\">\n";
+    let mut start: Option<usize> = None;
+    for idx in 0..=entropies.len() {
+        let invalid = idx < entropies.len() && entropies[idx].is_none();
+        match (invalid, start) {
+            (true, None) => start = Some(idx),
+            (false, Some(first)) => {
+                svg += &format!("<rect x=\"{:.2}\" y=\"{MARGIN_TOP}\" width=\"{:.2}\" height=\"{PLOT_HEIGHT}\"/>\n",
+                                x_of(first as f64), x_of(idx as f64) - x_of(first as f64));
+                start = None;
+            },
+            _ => {}
+        }
+    }
+    svg += "</g>\n";
+
+    // axes, ticks and labels
+    svg += &format!("<g stroke=\"black\" fill=\"none\">\n<path d=\"M{MARGIN_LEFT} {MARGIN_TOP}V{bottom}H{right}\"/>\n",
+                    bottom=MARGIN_TOP + PLOT_HEIGHT, right=WIDTH - MARGIN_RIGHT);
+    let x_step = tick_step(length as f64, 10.0);
+    let x_ticks: Vec<f64> = (1..).map(|x| x as f64 * x_step).take_while(|x| *x <= length as f64).collect();
+    let y_ticks: Vec<f64> = (0..).map(|x| x as f64 * 0.5).take_while(|x| *x <= maximum_entropy).collect();
+    x_ticks.iter().for_each(|x| svg += &format!("<path d=\"M{:.2} {bottom}v5\"/>\n", x_of(x - 0.5),
+                                                 bottom=MARGIN_TOP + PLOT_HEIGHT));
+    y_ticks.iter().for_each(|y| svg += &format!("<path d=\"M{MARGIN_LEFT} {:.2}h-5\"/>\n", y_of(*y)));
+    svg += "</g>\n<g fill=\"black\">\n";
+    x_ticks.iter().for_each(|x| svg += &format!("<text x=\"{:.2}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n",
+                                                 x_of(x - 0.5), MARGIN_TOP + PLOT_HEIGHT + 18.0, x));
+    y_ticks.iter().for_each(|y| svg += &format!("<text x=\"{}\" y=\"{:.2}\" text-anchor=\"end\" \
+                                                 dominant-baseline=\"middle\">{}</text>\n",
+                                                 MARGIN_LEFT - 8.0, y_of(*y), y));
+    svg += &format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">Position</text>\n",
+                    MARGIN_LEFT + plot_width/2.0, MARGIN_TOP + PLOT_HEIGHT + 36.0);
+    svg += &format!("<text transform=\"translate(20 {}) rotate(-90)\" text-anchor=\"middle\">Shannon entropy (bits)</text>\n",
+                    MARGIN_TOP + PLOT_HEIGHT/2.0);
+    svg += "</g>\n";
+
+    // entropy profile, broken at invalid positions
+    let profile = profile_path(entropies.iter().copied(), &x_of, &y_of);
+    svg += &format!("<path d=\"{profile}\" fill=\"none\" stroke=\"#1f77b4\" stroke-width=\"1\"/>\n");
+    if let Some(window_size) = cli.window_size {
+        let window_means = window_means(entropies, window_size);
+        let window_profile = profile_path(window_means.into_iter(), &x_of, &y_of);
+        svg += &format!("<path d=\"{window_profile}\" fill=\"none\" stroke=\"#d62728\" stroke-width=\"2\"/>\n");
+    }
+    if let Some(cutoff) = cli.plot_threshold {
+        svg += &format!("<path d=\"M{MARGIN_LEFT} {y:.2}H{right}\" stroke=\"#2ca02c\" stroke-dasharray=\"6 4\"/>\n",
+                        y=y_of(cutoff.min(maximum_entropy)), right=WIDTH - MARGIN_RIGHT);
+    }
+
+    // gene track
+    genes.iter().for_each(|(name, strand, first, last, row)| {
+        let x = x_of(*first as f64);
+        let width = (x_of(*last as f64 + 1.0) - x).max(1.0);
+        let y = track_top + *row as f64 * TRACK_ROW_HEIGHT;
+        let fill = if *strand == '-' { "#9467bd" } else { "#ff7f0e" };
+        svg += &format!("<g><title>{name} ({strand})</title><rect x=\"{x:.2}\" y=\"{y:.2}\" width=\"{width:.2}\" \
+                         height=\"{h}\" fill=\"{fill}\"/><text x=\"{tx:.2}\" y=\"{ty:.2}\" font-size=\"10\" \
+                         text-anchor=\"middle\">{name}</text></g>\n",
+                        name=escape_markup(name), h=TRACK_ROW_HEIGHT - 6.0, tx=x + width/2.0,
+                        ty=y + TRACK_ROW_HEIGHT - 9.0);
+    });
+    svg += "</svg>\n";
+    svg
+}
+
+pub fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// round tick step (1, 2 or 5 times a power of 10) giving about the requested number of ticks
+pub fn tick_step(range: f64, ticks: f64) -> f64 {
+    let raw = (range/ticks).max(1.0);
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].iter()
+        .map(|x| x * magnitude)
+        .find(|x| *x >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
+
+fn profile_path<I, X, Y>(values: I, x_of: &X, y_of: &Y) -> String
+where I: Iterator<Item=Option<f64>>, X: Fn(f64) -> f64, Y: Fn(f64) -> f64 {
+    let mut path = String::new();
+    let mut drawing = false;
+    values.enumerate().for_each(|(idx, value)| match value {
+        Some(entropy) if entropy.is_finite() => {
+            path += &format!("{}{:.2} {:.2}", if drawing { "L" } else { "M" },
+                             x_of(idx as f64 + 0.5), y_of(entropy));
+            drawing = true;
+        },
+        _ => drawing = false
+    });
+    path
+}
+
+// mean of the valid entropies in a window centred on every position
+fn window_means(entropies: &[Option<f64>], window_size: usize) -> Vec<Option<f64>> {
+    let mut sums = vec![0.0f64; entropies.len() + 1];
+    let mut counts = vec![0usize; entropies.len() + 1];
+    entropies.iter().enumerate().for_each(|(idx, x)| {
+        sums[idx + 1] = sums[idx] + x.unwrap_or(0.0);
+        counts[idx + 1] = counts[idx] + x.is_some() as usize;
+    });
+    (0..entropies.len()).map(|idx| {
+        let first = idx.saturating_sub(window_size/2);
+        let last = (first + window_size).min(entropies.len());
+        let count = counts[last] - counts[first];
+        if count > 0 {
+            Some((sums[last] - sums[first])/count as f64)
+        } else {
+            None
+        }
+    }).collect()
+}
+
+// CDS features in alignment columns, assigned to the first row without overlap
+fn gene_rows(reference: &Reference, annotation: &Annotation) -> Vec<(String, char, usize, usize, usize)> {
+    let mut spans: Vec<(String, char, usize, usize)> = annotation.coding.iter()
+        .filter_map(|cds| {
+            let first = cds.positions.iter().min()?;
+            let last = cds.positions.iter().max()?;
+            Some((cds.name.clone(), cds.strand,
+                  *reference.columns.get(first - 1)?, *reference.columns.get(last - 1)?))
+        })
+        .collect();
+    spans.sort_by_key(|x| (x.2, x.3));
+    // last column used in every row
+    let mut row_ends: Vec<usize> = Vec::new();
+    spans.into_iter().map(|(name, strand, first, last)| {
+        let row = match row_ends.iter().position(|end| *end < first) {
+            Some(row) => {
+                row_ends[row] = last;
+                row
+            },
+            None => {
+                row_ends.push(last);
+                row_ends.len() - 1
+            }
+        };
+        (name, strand, first, last, row)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::bin_utils::testing::entropy_args;
+
+    #[test]
+    fn profile_of_a_fixed_column_set() {
+        let cli = entropy_args(Path::new("a.fasta"), &["--plot"]);
+        let svg = entropy_svg(&[Some(0.0), None, None, Some(2.0), Some(1.0)], "a<b", &cli, &[]);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1200\" height=\"405\""));
+        assert!(svg.contains("<title>Shannon entropy of a&lt;b</title>"));
+        // the background and one run of invalid positions
+        assert_eq!(svg.matches("<rect").count(), 2);
+        assert!(svg.contains("<rect x=\"292.00\" y=\"40\" width=\"444.00\" height=\"300\"/>"));
+        // 0 bits at the bottom, the 2 bits of 'Standard' mode at the top, broken at the invalid run
+        assert!(svg.contains("<path d=\"M181.00 340.00M847.00 40.00L1069.00 190.00\""));
+        // x ticks 1 to 5, y ticks 0 to 2 by 0.5
+        assert_eq!(svg.matches("v5\"/>").count(), 5);
+        assert_eq!(svg.matches("h-5\"/>").count(), 5);
+        assert!(!svg.contains("stroke-dasharray"));
+    }
+
+    #[test]
+    fn gene_track_adds_rows() {
+        let cli = entropy_args(Path::new("a.fasta"), &["--plot", "--plot-threshold", "1.5", "--window-size", "3"]);
+        let genes = [(String::from("ORF1"), '+', 0, 2, 0), (String::from("ORF2"), '-', 1, 4, 1)];
+        let svg = entropy_svg(&[Some(0.5); 5], "a", &cli, &genes);
+        assert!(svg.contains("height=\"445\""));
+        assert_eq!(svg.matches("<title>ORF").count(), 2);
+        assert!(svg.contains("<title>ORF2 (-)</title><rect x=\"292.00\" y=\"405.00\" width=\"888.00\""));
+        assert!(svg.contains("<path d=\"M70 115.00H1180\" stroke=\"#2ca02c\""));
+        assert!(svg.contains("stroke=\"#d62728\""));
+    }
+
+    #[test]
+    fn window_means_skip_invalid_positions() {
+        assert_eq!(window_means(&[Some(1.0), None, Some(3.0), None], 3),
+                   [Some(2.0), Some(2.0), Some(3.0), Some(3.0)]);
+        assert_eq!(window_means(&[None, None, Some(1.0)], 1), [None, None, Some(1.0)]);
+    }
+
+    #[test]
+    fn round_tick_steps() {
+        assert_eq!(tick_step(5.0, 10.0), 1.0);
+        assert_eq!(tick_step(29903.0, 10.0), 5000.0);
+        assert_eq!(tick_step(150.0, 10.0), 20.0);
+    }
+}
@@ -0,0 +1,225 @@
+/*
+    Population genetic summary statistics from the column counts
+        Only valid positions are considered, alleles are A, T, G and C
+        Per position
+            n: number of sequences with an allele at the position
+            pi = n/(n-1) (1 - sum p^2)
+            segregating if two or more alleles are present
+        Per region (whole alignment, sliding windows)
+            nucleotide diversity: sum of pi, and per position
+            Watterson's theta: sum over segregating positions of 1/a1(n)
+            Tajima's D (Tajima 1989) from pi and Watterson's theta
+                n of the variance: mean n of the segregating positions, rounded
+                    per position n as for pi and theta, gaps and ambiguous bases leave sequences out
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{considered_notations, create_output, notation_counts, output_name};
+use super::get_args::EntropyArgs;
+
+const ALLELES: &str = "ATGC";
+
+#[derive(Clone, Copy)]
+pub struct SiteDiversity {
+    pub sample_size: f64,
+    pub pi: f64,
+    pub segregating: bool,
+}
+
+pub struct RegionStatistics {
+    pub start: usize,
+    pub end: usize,
+    pub sites: usize,
+    pub segregating_sites: usize,
+    pub pi: f64,
+    pub theta_w: f64,
+    pub tajima_d: Option<f64>,
+}
+
+pub fn report_popgen(map_vec: &[HashMap<char, f64>], genome_count: f64,
+                     file: &FileBufferHelper, cli: &EntropyArgs) -> Result<(), String> {
+    let sites = site_diversity(map_vec, genome_count, cli);
+    let alignment = region_statistics(&sites, 0, sites.len());
+    info!("Nucleotide diversity {}, {} segregating sites, Tajima's D {:?}",
+        alignment.pi, alignment.segregating_sites, alignment.tajima_d);
+    write_regions(&[alignment], file, cli, "popgen_statistics")?;
+    if let Some(window_size) = cli.window_size {
+        let windows = windows(sites.len(), window_size, cli.window_step.unwrap_or(window_size))
+                        .into_iter()
+                        .map(|(start, end)| region_statistics(&sites, start, end))
+                        .collect::<Vec<RegionStatistics>>();
+        info!("Population genetic statistics for {} windows", windows.len());
+        write_regions(&windows, file, cli, "popgen_windows")?;
+    }
+    Ok(())
+}
+
+pub fn windows(length: usize, window_size: usize, window_step: usize) -> Vec<(usize, usize)> {
+    // 0-based start and exclusive end of sliding windows over the alignment
+    // the last window is cut short at the end of the alignment
+    let mut windows: Vec<(usize, usize)> = Vec::new();
+    let mut start = 0usize;
+    while start < length || windows.is_empty() {
+        let end = (start + window_size).min(length);
+        windows.push((start, end));
+        if end == length {
+            break
+        }
+        start += window_step.max(1);
+    }
+    windows
+}
+
+pub fn site_diversity(map_vec: &[HashMap<char, f64>], genome_count: f64,
+                      cli: &EntropyArgs) -> Vec<Option<SiteDiversity>> {
+    // diversity at valid positions, None for invalid positions
+    let atgc = considered_notations(&cli.counting.mode);
+    map_vec.iter().map(|char_map| {
+        let atgc_share: f64 = notation_counts(char_map, atgc).iter().sum();
+        if atgc_share/genome_count < cli.counting.threshold {
+            return None
+        }
+        let allele_counts = notation_counts(char_map, ALLELES);
+        let sample_size: f64 = allele_counts.iter().sum();
+        if sample_size < 2.0 {
+            return None
+        }
+        let homozygosity: f64 = allele_counts.iter()
+                                    .map(|x| (x/sample_size).powi(2))
+                                    .sum();
+        Some(SiteDiversity {
+            sample_size,
+            pi: sample_size/(sample_size - 1.0) * (1.0 - homozygosity),
+            segregating: allele_counts.iter().filter(|&&x| x > 0.0).count() > 1,
+        })
+    }).collect()
+}
+
+fn harmonic(n: usize, power: i32) -> f64 {
+    (1..n).map(|x| 1.0/(x as f64).powi(power)).sum()
+}
+
+pub fn region_statistics(sites: &[Option<SiteDiversity>], start: usize, end: usize) -> RegionStatistics {
+    let region: Vec<&SiteDiversity> = sites[start..end].iter().flatten().collect();
+    let segregating: Vec<&&SiteDiversity> = region.iter().filter(|x| x.segregating).collect();
+    let pi: f64 = region.iter().map(|x| x.pi).sum();
+    let theta_w: f64 = segregating.iter()
+                        .map(|x| 1.0/harmonic(x.sample_size as usize, 1))
+                        .sum();
+    let sample_size = match segregating.len() {
+        0 => 0,
+        x => (segregating.iter().map(|x| x.sample_size).sum::<f64>()/x as f64).round() as usize
+    };
+    RegionStatistics {
+        start: start + 1,
+        end,
+        sites: region.len(),
+        segregating_sites: segregating.len(),
+        pi,
+        theta_w,
+        tajima_d: tajima_d(pi, theta_w, segregating.len(), sample_size),
+    }
+}
+
+fn tajima_d(pi: f64, theta_w: f64, segregating_sites: usize, n: usize) -> Option<f64> {
+    if segregating_sites == 0 || n < 4 {
+        return None
+    }
+    let s = segregating_sites as f64;
+    let n_f64 = n as f64;
+    let a1 = harmonic(n, 1);
+    let a2 = harmonic(n, 2);
+    let b1 = (n_f64 + 1.0)/(3.0 * (n_f64 - 1.0));
+    let b2 = 2.0 * (n_f64 * n_f64 + n_f64 + 3.0)/(9.0 * n_f64 * (n_f64 - 1.0));
+    let c1 = b1 - 1.0/a1;
+    let c2 = b2 - (n_f64 + 2.0)/(a1 * n_f64) + a2/(a1 * a1);
+    let e1 = c1/a1;
+    let e2 = c2/(a1 * a1 + a2);
+    let variance = e1 * s + e2 * s * (s - 1.0);
+    if variance > 0.0 {
+        Some((pi - theta_w)/variance.sqrt())
+    } else {
+        None
+    }
+}
+
+fn write_regions(regions: &[RegionStatistics], file: &FileBufferHelper, cli: &EntropyArgs,
+                 metric: &str) -> Result<(), String> {
+    let out_file_name = output_name(file, &cli.output, metric);
+    info!("Population genetic statistics output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut popgen_writer = BufWriter::new(out_file);
+    writeln!(popgen_writer, "Start{delim}End{delim}Sites{delim}Segregating_sites{delim}\
+                             Nucleotide_diversity{delim}Nucleotide_diversity_per_site{delim}\
+                             Watterson_theta{delim}Watterson_theta_per_site{delim}Tajima_D",
+             delim=cli.output.delimiter).expect("Unable to write to file");
+    regions.iter().for_each(|region| {
+        let per_site = |x: f64| if region.sites > 0 {
+            (x/region.sites as f64).to_string()
+        } else {
+            String::from("NA")
+        };
+        writeln!(popgen_writer, "{start}{delim}{end}{delim}{sites}{delim}{segregating}{delim}\
+                                 {pi}{delim}{pi_per_site}{delim}{theta_w}{delim}\
+                                 {theta_w_per_site}{delim}{tajima_d}",
+                 start=region.start, end=region.end, sites=region.sites,
+                 segregating=region.segregating_sites, pi=region.pi,
+                 pi_per_site=per_site(region.pi), theta_w=region.theta_w,
+                 theta_w_per_site=per_site(region.theta_w),
+                 tajima_d=region.tajima_d.map_or(String::from("NA"), |x| x.to_string()),
+                 delim=cli.output.delimiter).expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(sample_size: f64, pi: f64) -> Option<SiteDiversity> {
+        Some(SiteDiversity { sample_size, pi, segregating: pi > 0.0 })
+    }
+
+    #[test]
+    fn tajima_d_textbook_example() {
+        // n = 10 sequences, S = 16 segregating sites, pi = 3.888889: D = -1.446172
+        let theta_w = 16.0/harmonic(10, 1);
+        assert!((theta_w - 5.655772).abs() < 1e-6);
+        let d = tajima_d(3.888889, theta_w, 16, 10).unwrap();
+        assert!((d - -1.446172).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tajima_d_is_undefined_without_segregating_sites_or_few_sequences() {
+        assert_eq!(tajima_d(0.0, 0.0, 0, 10), None);
+        assert_eq!(tajima_d(1.0, 1.0, 1, 3), None);
+    }
+
+    #[test]
+    fn watterson_theta_uses_the_sample_size_of_each_position() {
+        // 1/a1(4) + 1/a1(3) with a1(4) = 11/6 and a1(3) = 3/2
+        let sites = [site(4.0, 0.5), site(3.0, 2.0/3.0), site(4.0, 0.0), None];
+        let region = region_statistics(&sites, 0, sites.len());
+        assert_eq!(region.sites, 3);
+        assert_eq!(region.segregating_sites, 2);
+        assert!((region.pi - 7.0/6.0).abs() < 1e-12);
+        assert!((region.theta_w - (6.0/11.0 + 2.0/3.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tajima_d_of_complete_positions_matches_the_number_of_sequences() {
+        // without missing data the region statistic is the textbook D
+        let sites: Vec<Option<SiteDiversity>> = (0..16).map(|_| site(10.0, 3.888889/16.0)).collect();
+        let region = region_statistics(&sites, 0, sites.len());
+        assert!((region.tajima_d.unwrap() - -1.446172).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sliding_windows() {
+        assert_eq!(windows(10, 4, 3), vec![(0, 4), (3, 7), (6, 10)]);
+        assert_eq!(windows(3, 5, 5), vec![(0, 3)]);
+    }
+}
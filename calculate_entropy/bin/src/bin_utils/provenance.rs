@@ -0,0 +1,142 @@
+/*
+    Provenance of the outputs of an input alignment, as a JSON sidecar file written with --provenance
+        tool name and version
+        command line as given and the resolved options of the subcommand
+            every option with a value, from the configuration file, the command line or the defaults
+            configuration file path, size in bytes and SHA-256 checksum, null without --config
+        input file path, size in bytes and SHA-256 checksum
+        alignment dimensions: sequences and positions
+        time of the run in UTC (ISO 8601)
+*/
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::info;
+use sha2::{Digest, Sha256};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{create_output, output_name};
+use super::config::{is_number, run_configuration, ResolvedOption};
+use super::get_args::OutputOptions;
+use super::output_format::json_string;
+use super::time_bins::{civil_from_days, format_date};
+
+pub fn write_provenance(positions: usize, genome_count: f64, file: &FileBufferHelper,
+                        output: &OutputOptions) -> Result<(), String> {
+    let (size, checksum) = file_checksum(file.path)
+                            .map_err(|e| format!("Unable to read {:?} - {}", file.path, e))?;
+    let arguments = std::env::args()
+                        .map(|x| json_string(&x))
+                        .collect::<Vec<String>>()
+                        .join(",");
+    let options = run_configuration().map_or(String::new(), |x| options_json(&x.options));
+    let config = match run_configuration().and_then(|x| x.config.as_ref()) {
+        Some(path) => {
+            let (size, checksum) = file_checksum(path)
+                                    .map_err(|e| format!("Unable to read {:?} - {}", path, e))?;
+            format!("{{\"path\":{},\"size\":{size},\"sha256\":\"{checksum}\"}}",
+                    json_string(&path.to_string_lossy()))
+        },
+        None => String::from("null")
+    };
+
+    let out_file_name = output_name(file, output, "provenance.json");
+    info!("Provenance output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, output)?;
+    let mut provenance_writer = BufWriter::new(out_file);
+    writeln!(provenance_writer, "{{\n  \"tool\":{{\"name\":{name},\"version\":{version}}},\n  \
+                                 \"arguments\":[{arguments}],\n  \"options\":{{{options}}},\n  \
+                                 \"config\":{config},\n  \
+                                 \"input\":{{\"path\":{path},\"size\":{size},\"sha256\":\"{checksum}\"}},\n  \
+                                 \"alignment\":{{\"sequences\":{genome_count},\"positions\":{positions}}},\n  \
+                                 \"timestamp\":\"{timestamp}\"\n}}",
+             name=json_string(env!("CARGO_PKG_NAME")), version=json_string(env!("CARGO_PKG_VERSION")),
+             path=json_string(&file.path.to_string_lossy()), timestamp=utc_timestamp())
+        .expect("Unable to write to file");
+    Ok(())
+}
+
+// resolved options of the subcommand as JSON members, flags and numbers unquoted
+fn options_json(options: &[ResolvedOption]) -> String {
+    options.iter().map(|option| {
+        let values: Vec<String> = option.values.iter()
+                                    .map(|x| if option.flag || is_number(x) { x.clone() } else { json_string(x) })
+                                    .collect();
+        let value = if option.multiple {
+            format!("[{}]", values.join(","))
+        } else {
+            values.first().cloned().unwrap_or(String::from("null"))
+        };
+        format!("{}:{}", json_string(&option.name), value)
+    }).collect::<Vec<String>>().join(",")
+}
+
+// size in bytes and hex encoded SHA-256 of a file
+fn file_checksum(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut input = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    let mut size = 0u64;
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    let checksum = hasher.finalize().iter().map(|x| format!("{x:02x}")).collect();
+    Ok((size, checksum))
+}
+
+// current time as YYYY-MM-DDTHH:MM:SSZ
+fn utc_timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs()) as i64;
+    let day_seconds = seconds.rem_euclid(86400);
+    format!("{}T{:02}:{:02}:{:02}Z", format_date(civil_from_days(seconds.div_euclid(86400))),
+            day_seconds/3600, day_seconds % 3600/60, day_seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_utils::testing::TestDir;
+
+    #[test]
+    fn checksum_of_known_bytes() {
+        let dir = TestDir::new("checksum");
+        let (size, checksum) = file_checksum(&dir.file("abc", "abc")).unwrap();
+        assert_eq!(size, 3);
+        assert_eq!(checksum, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        let (size, checksum) = file_checksum(&dir.file("empty", "")).unwrap();
+        assert_eq!(size, 0);
+        assert_eq!(checksum, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn options_are_json_members() {
+        let resolved = |name: &str, values: &[&str], flag: bool, multiple: bool| ResolvedOption {
+            name: name.to_string(),
+            values: values.iter().map(|x| x.to_string()).collect(),
+            flag,
+            multiple,
+        };
+        let options = [resolved("mode", &["standard"], false, false),
+                       resolved("threshold", &["0.8"], false, false),
+                       resolved("delimiter", &["\t"], false, false),
+                       resolved("infile", &["a.fasta", "b \"c\".fasta"], false, true),
+                       resolved("codon-entropy", &["true"], true, false)];
+        let json = format!("{{{}}}", options_json(&options));
+        assert_eq!(json, "{\"mode\":\"standard\",\"threshold\":0.8,\"delimiter\":\"\\t\",\
+                          \"infile\":[\"a.fasta\",\"b \\\"c\\\".fasta\"],\"codon-entropy\":true}");
+        // JSON is valid YAML, read back with the YAML parser of the configuration
+        let parsed: serde_yaml::Mapping = serde_yaml::from_str(&json).unwrap();
+        let keys: Vec<&str> = parsed.keys().map(|x| x.as_str().unwrap()).collect();
+        assert_eq!(keys, ["mode", "threshold", "delimiter", "infile", "codon-entropy"]);
+        assert_eq!(parsed["threshold"].as_f64(), Some(0.8));
+        assert_eq!(parsed["delimiter"].as_str(), Some("\t"));
+        assert_eq!(parsed["infile"][1].as_str(), Some("b \"c\".fasta"));
+        assert_eq!(parsed["codon-entropy"].as_bool(), Some(true));
+    }
+}
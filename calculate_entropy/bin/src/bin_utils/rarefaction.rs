@@ -0,0 +1,216 @@
+/*
+    Rarefied Shannon entropy, comparable between alignments of different sizes
+        For every sample size and position
+            draw sequences without replacement from the column counts, repeated for the replicates
+                one multivariate hypergeometric draw over the notations of the column
+            entropy of the considered notations of every draw
+                draws without considered notations are left out
+            expected entropy: mean over the draws, with the standard deviation
+        Sample sizes above the number of sequences are skipped
+        Every position is drawn with its own generator, seeded from the seed and the position
+    Write the rarefaction curve of every position
+    Optionally average the curves over sliding windows
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::sync::{mpsc, Arc};
+use threadpool::ThreadPool;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Hypergeometric};
+use log::{info, warn};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{considered_notations, create_output, get_entropy, output_name};
+use super::get_args::EntropyArgs;
+use super::popgen::windows;
+
+struct RarefiedEntropy {
+    // mean and standard deviation of every sample size, None without a draw of considered notations
+    curve: Vec<Option<(f64, f64)>>,
+}
+
+pub fn report_rarefaction(map_vec: &[HashMap<char, f64>], genome_count: f64,
+                          file: &FileBufferHelper, cli: &EntropyArgs) -> Result<(), String> {
+    let sample_sizes: Vec<usize> = cli.rarefaction.iter().copied()
+        .filter(|x| {
+            if *x as f64 > genome_count {
+                warn!("Sample size {} is above the {} sequences of {:?}, skipped", x, genome_count, file.path);
+            }
+            *x as f64 <= genome_count
+        })
+        .collect();
+    if sample_sizes.is_empty() {
+        return Err(format!("No rarefaction sample size up to the {} sequences of {:?}",
+                           genome_count, file.path))
+    }
+    let seed = cli.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    info!("Rarefaction seed: {}", seed);
+    let curves = rarefied_entropies(map_vec, &sample_sizes, seed, cli);
+
+    let out_file_name = output_name(file, &cli.output, "rarefaction");
+    info!("Rarefaction output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut rarefaction_writer = BufWriter::new(out_file);
+    writeln!(rarefaction_writer, "Position{delim}Sample_size{delim}Replicates{delim}\
+                                  Expected_entropy{delim}Entropy_sd", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    curves.iter().enumerate().for_each(|(idx, x)| {
+        sample_sizes.iter().zip(x.curve.iter()).for_each(|(sample_size, point)| {
+            let (mean, sd) = point.map_or((String::from("NA"), String::from("NA")),
+                                          |(mean, sd)| (mean.to_string(), sd.to_string()));
+            writeln!(rarefaction_writer, "{pos}{delim}{sample_size}{delim}{replicates}{delim}{mean}{delim}{sd}",
+                     pos=idx + 1, replicates=cli.rarefaction_replicates, delim=cli.output.delimiter)
+                .expect("Unable to write to file");
+        });
+    });
+
+    if let Some(window_size) = cli.window_size {
+        let windows = windows(map_vec.len(), window_size, cli.window_step.unwrap_or(window_size));
+        write_windows(&curves, &sample_sizes, &windows, file, cli)?;
+    }
+    Ok(())
+}
+
+fn rarefied_entropies(map_vec: &[HashMap<char, f64>], sample_sizes: &[usize], seed: u64,
+                      cli: &EntropyArgs) -> Vec<RarefiedEntropy> {
+    // one job per position
+    let notations = considered_notations(&cli.counting.mode);
+    let pool = ThreadPool::new(cli.counting.nproc);
+    let (sender, receiver) = mpsc::channel();
+    let sample_sizes = Arc::new(sample_sizes.to_vec());
+    let replicates = cli.rarefaction_replicates;
+    map_vec.iter().enumerate().for_each(|(position, char_map)| {
+        // notation counts in a fixed order, flagged when considered
+        let mut column: Vec<(char, u64)> = char_map.iter()
+                                            .map(|(x, count)| (*x, *count as u64))
+                                            .collect();
+        column.sort();
+        let considered: Vec<bool> = column.iter().map(|(x, _)| notations.contains(*x)).collect();
+        let counts: Vec<u64> = column.iter().map(|(_, count)| *count).collect();
+        let sample_sizes_clone = Arc::clone(&sample_sizes);
+        let sender_clone = sender.clone();
+        pool.execute(move || {
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(position as u64));
+            let curve = sample_sizes_clone.iter().map(|sample_size| {
+                let entropies: Vec<f64> = (0..replicates)
+                    .filter_map(|_| {
+                        let draw = hypergeometric_draw(&counts, *sample_size as u64, &mut rng);
+                        let considered_counts: Vec<f64> = draw.iter().zip(&considered)
+                                                            .filter(|(_, x)| **x)
+                                                            .map(|(count, _)| *count as f64)
+                                                            .collect();
+                        if considered_counts.iter().sum::<f64>() > 0.0 {
+                            Some(get_entropy(&considered_counts))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                if entropies.is_empty() {
+                    return None
+                }
+                let draws = entropies.len() as f64;
+                let mean = entropies.iter().sum::<f64>()/draws;
+                let variance = entropies.iter().map(|x| (x - mean).powi(2)).sum::<f64>()/draws;
+                Some((mean, variance.sqrt()))
+            }).collect();
+            sender_clone.send((position, RarefiedEntropy { curve }))
+                .expect("Unable to send rarefied entropy");
+        });
+    });
+    drop(sender);
+    let mut curves: Vec<(usize, RarefiedEntropy)> = receiver.iter().collect();
+    curves.sort_by_key(|(position, _)| *position);
+    curves.into_iter().map(|(_, x)| x).collect()
+}
+
+// counts of every notation among sample_size sequences drawn without replacement
+fn hypergeometric_draw(counts: &[u64], sample_size: u64, rng: &mut StdRng) -> Vec<u64> {
+    let mut remaining_population: u64 = counts.iter().sum();
+    let mut remaining_sample = sample_size.min(remaining_population);
+    counts.iter().map(|count| {
+        let drawn = if remaining_sample == 0 || *count == 0 {
+            0
+        } else if *count == remaining_population {
+            remaining_sample
+        } else {
+            Hypergeometric::new(remaining_population, *count, remaining_sample)
+                .expect("Invalid hypergeometric parameters")
+                .sample(rng)
+        };
+        remaining_population -= count;
+        remaining_sample -= drawn;
+        drawn
+    }).collect()
+}
+
+fn write_windows(curves: &[RarefiedEntropy], sample_sizes: &[usize], windows: &[(usize, usize)],
+                 file: &FileBufferHelper, cli: &EntropyArgs) -> Result<(), String> {
+    let out_file_name = output_name(file, &cli.output, "rarefaction_windows");
+    info!("Windowed rarefaction output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut window_writer = BufWriter::new(out_file);
+    writeln!(window_writer, "Start{delim}End{delim}Sample_size{delim}Positions{delim}\
+                             Mean_expected_entropy", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    windows.iter().for_each(|(start, end)| {
+        sample_sizes.iter().enumerate().for_each(|(size_idx, sample_size)| {
+            let expected: Vec<f64> = curves[*start..*end].iter()
+                                        .filter_map(|x| x.curve[size_idx].map(|(mean, _)| mean))
+                                        .collect();
+            let mean = if expected.is_empty() {
+                String::from("NA")
+            } else {
+                (expected.iter().sum::<f64>()/expected.len() as f64).to_string()
+            };
+            writeln!(window_writer, "{first}{delim}{end}{delim}{sample_size}{delim}{positions}{delim}{mean}",
+                     first=start + 1, positions=expected.len(), delim=cli.output.delimiter)
+                .expect("Unable to write to file");
+        });
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    #[test]
+    fn draws_sum_to_the_sample_size() {
+        let counts = [5, 0, 3, 12, 1];
+        let mut rng = StdRng::seed_from_u64(11);
+        (0..=21).for_each(|sample_size| (0..20).for_each(|_| {
+            let draw = hypergeometric_draw(&counts, sample_size, &mut rng);
+            assert_eq!(draw.iter().sum::<u64>(), sample_size);
+            draw.iter().zip(counts).for_each(|(drawn, count)| assert!(*drawn <= count));
+        }));
+    }
+
+    #[test]
+    fn sample_of_every_sequence_keeps_the_counts() {
+        let counts = [5, 0, 3, 12, 1];
+        let mut rng = StdRng::seed_from_u64(11);
+        assert_eq!(hypergeometric_draw(&counts, 21, &mut rng), counts);
+        assert_eq!(hypergeometric_draw(&counts, 30, &mut rng), counts);
+    }
+
+    #[test]
+    fn rarefied_entropy_is_seeded() {
+        let dir = TestDir::new("rarefaction");
+        let path = dir.alignment("a.fasta", &[("s1", "AAAA")]);
+        let (map_vec, _) = counts(&["ACGT", "ACGA", "ACCA", "AGCA", "ATCA", "A-CA"], &Mode::Standard);
+        let cli = entropy_args(&path, &["--rarefaction", "3,6"]);
+        let curves = rarefied_entropies(&map_vec, &[3, 6], 5, &cli);
+        // the sample of every sequence is the entropy of the column, without spread
+        assert_eq!(curves[0].curve[1], Some((0.0, 0.0)));
+        let (mean, sd) = curves[2].curve[1].unwrap();
+        assert!((mean - get_entropy(&[2.0, 4.0])).abs() < 1e-12);
+        assert!(sd < 1e-12);
+        // the same seed draws the same samples
+        let curve = |x: Vec<RarefiedEntropy>| x.into_iter().map(|x| x.curve).collect::<Vec<_>>();
+        assert_eq!(curve(curves), curve(rarefied_entropies(&map_vec, &[3, 6], 5, &cli)));
+    }
+}
@@ -0,0 +1,109 @@
+/*
+    Map alignment columns to reference coordinates
+        Pick the reference sequence from the alignment
+            header matching the requested ID
+            first sequence of the alignment if no ID is given
+        Walk along the aligned reference
+            gap in reference: column has no reference position
+            otherwise: increment reference position
+*/
+
+use log::{error, info};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+
+const REFERENCE_GAPS: &str = "-.";
+
+pub struct Reference {
+    // fasta header of the reference, without ">"
+    pub header: String,
+    // aligned reference sequence in upper case, gaps included
+    pub sequence: String,
+    // alignment column -> 1-based reference position
+    pub positions: Vec<Option<usize>>,
+    // 1-based reference position - 1 -> alignment column
+    pub columns: Vec<usize>,
+}
+
+impl Reference {
+    pub fn reference_position(&self, column: usize) -> Option<usize> {
+        // reference position of a 0-based alignment column
+        self.positions.get(column).copied().flatten()
+    }
+}
+
+pub fn load_reference(file: &mut FileBufferHelper, reference_id: Option<&String>)
+                                                            -> Result<Reference, String> {
+    // find the reference sequence in the alignment
+    // exact header match is preferred, else the first header containing the ID
+    file.buffer_reset();
+    let mut found: Option<(String, String)> = None;
+    while let Some((header, sequence)) = file.next_record() {
+        match reference_id {
+            None => {
+                found = Some((header, sequence));
+                break
+            },
+            Some(id) if &header == id => {
+                found = Some((header, sequence));
+                break
+            },
+            Some(id) if found.is_none() && header.contains(id.as_str()) => {
+                found = Some((header, sequence));
+            },
+            Some(_) => {}
+        }
+    }
+    file.buffer_reset();
+    let (header, sequence) = match found {
+        Some(record) => record,
+        None => {
+            error!("Reference {:?} not found in {:?}", reference_id, file.path);
+            return Err(format!("Reference {:?} not found in alignment", reference_id))
+        }
+    };
+    info!("Reference sequence: {}", header);
+    let sequence = sequence.to_ascii_uppercase();
+    let mut positions: Vec<Option<usize>> = Vec::with_capacity(sequence.len());
+    let mut columns: Vec<usize> = Vec::new();
+    sequence.chars().enumerate().for_each(|(idx, base)| {
+        if REFERENCE_GAPS.contains(base) {
+            positions.push(None);
+        } else {
+            columns.push(idx);
+            positions.push(Some(columns.len()));
+        }
+    });
+    info!("Reference spans {} of {} alignment columns", columns.len(), sequence.len());
+    Ok(Reference {
+        header,
+        sequence,
+        positions,
+        columns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::testing::TestDir;
+
+    #[test]
+    fn reference_positions_skip_gaps() {
+        let dir = TestDir::new("reference");
+        let path = dir.alignment("a.fasta", &[("s1 first", "ACGTAC"), ("ref2 second", "a-cg.t")]);
+        let mut file = check_fasta(&path, true).unwrap();
+        let reference = load_reference(&mut file, None).unwrap();
+        assert_eq!(reference.header, "s1 first");
+        assert_eq!(reference.columns, [0, 1, 2, 3, 4, 5]);
+        // exact header, then the first header containing the ID
+        let reference = load_reference(&mut file, Some(&String::from("ref2"))).unwrap();
+        assert_eq!(reference.header, "ref2 second");
+        assert_eq!(reference.sequence, "A-CG.T");
+        assert_eq!(reference.positions, [Some(1), None, Some(2), Some(3), None, Some(4)]);
+        assert_eq!(reference.columns, [0, 2, 3, 5]);
+        assert_eq!(reference.reference_position(4), None);
+        assert_eq!(reference.reference_position(5), Some(4));
+        assert!(load_reference(&mut file, Some(&String::from("missing"))).is_err());
+    }
+}
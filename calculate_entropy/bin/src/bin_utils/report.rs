@@ -0,0 +1,183 @@
+/*
+    Self contained HTML report of a run, one per input alignment
+        Inline styles and SVG only, no scripts or links, readable offline
+        Sections
+            alignment statistics: sequences, length, gap %, N %, from the sequences
+                characters outside the notations of the mode, as counted for the entropy
+            entropy profile plot
+            top N positions by entropy among the valid positions
+            run parameters: command line and the main settings
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::alleles::ranked_alleles;
+use super::annotation::Annotation;
+use super::calculate_entropy::{considered_notations, create_output, notation_counts, output_name, valid_entropies};
+use super::get_args::{EntropyArgs, Mode};
+use super::plot::{escape_markup, profile_svg};
+use super::reference::Reference;
+use super::write_entropies::alignment_composition;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+                     h1{font-size:1.5em}h2{font-size:1.2em;border-bottom:1px solid #ccc;padding-bottom:.2em}\
+                     table{border-collapse:collapse;margin:.5em 0}\
+                     td,th{border:1px solid #ccc;padding:.25em .6em;text-align:left}\
+                     th{background:#f3f3f3}td.num{text-align:right}svg{max-width:100%;height:auto}";
+
+// non_permissible: characters counted as "." for not being notations of the mode
+pub fn report_html(map_vec: &[HashMap<char, f64>], genome_count: f64, non_permissible: usize,
+                   file: &mut FileBufferHelper, cli: &EntropyArgs, reference: Option<&Reference>,
+                   annotation: Option<&Annotation>) -> Result<(), String> {
+    let composition = alignment_composition(file);
+    let cells: u64 = composition.values().sum();
+    let percent = |chars: &str| if cells > 0 {
+        100.0 * chars.chars().filter_map(|x| composition.get(&x)).sum::<u64>() as f64/cells as f64
+    } else {
+        0.0
+    };
+    let entropies = valid_entropies(map_vec, genome_count, &cli.counting);
+    let valid_positions = entropies.iter().filter(|x| x.is_some()).count();
+    let name = file.path.to_string_lossy().to_string();
+
+    let mut html = format!("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+                            <title>calculate_entropy report - {title}</title>\n<style>{STYLE}</style>\n\
+                            </head>\n<body>\n<h1>calculate_entropy report</h1>\n<p>{title}</p>\n",
+                           title=escape_markup(&name));
+
+    html += "<h2>Alignment statistics</h2>\n<table>\n";
+    [("Sequences", genome_count.to_string()),
+     ("Alignment length", map_vec.len().to_string()),
+     ("Valid positions", format!("{} (threshold {})", valid_positions, cli.counting.threshold)),
+     ("Invalid positions", (map_vec.len() - valid_positions).to_string()),
+     ("Gap % (- and .)", format!("{:.3}", percent("-."))),
+     ("N %", format!("{:.3}", percent("N"))),
+     ("Other ambiguity codes %", format!("{:.3}", percent("UWSMKRYBDHV"))),
+     ("Non-permissible characters", non_permissible.to_string())].iter()
+        .for_each(|(key, value)| html += &format!("<tr><th>{key}</th><td class=\"num\">{value}</td></tr>\n"));
+    html += "</table>\n";
+
+    html += "<h2>Entropy profile</h2>\n";
+    html += &profile_svg(map_vec, genome_count, file, cli, reference, annotation);
+
+    html += &format!("<h2>Top {} positions by entropy</h2>\n", cli.report_top);
+    html += &top_sites(map_vec, &entropies, genome_count, cli, reference, annotation);
+
+    html += "<h2>Run parameters</h2>\n<table>\n";
+    let mode = match cli.counting.mode {
+        Mode::Standard => "Standard",
+        Mode::All => "All"
+    };
+    [("Command line", std::env::args().collect::<Vec<String>>().join(" ")),
+     ("Version", env!("CARGO_PKG_VERSION").to_string()),
+     ("Mode", mode.to_string()),
+     ("Considered notations", considered_notations(&cli.counting.mode).to_string()),
+     ("Threshold", cli.counting.threshold.to_string()),
+     ("Output suffix", cli.output.output_suffix.clone()),
+     ("Threads", cli.counting.nproc.to_string())].iter()
+        .for_each(|(key, value)| html += &format!("<tr><th>{key}</th><td>{}</td></tr>\n", escape_markup(value)));
+    html += "</table>\n</body>\n</html>\n";
+
+    let out_file_name = output_name(file, &cli.output, "report.html");
+    info!("HTML report output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut report_writer = BufWriter::new(out_file);
+    write!(report_writer, "{}", html).expect("Unable to write to file");
+    Ok(())
+}
+
+fn top_sites(map_vec: &[HashMap<char, f64>], entropies: &[Option<f64>], genome_count: f64, cli: &EntropyArgs,
+             reference: Option<&Reference>, annotation: Option<&Annotation>) -> String {
+    let mut ranked: Vec<(usize, f64)> = entropies.iter().enumerate()
+                                        .filter_map(|(idx, x)| x.map(|entropy| (idx, entropy)))
+                                        .filter(|(_, entropy)| *entropy > 0.0)
+                                        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    if ranked.is_empty() {
+        return String::from("<p>No variable valid positions.</p>\n")
+    }
+    let notations = considered_notations(&cli.counting.mode);
+    let mut table = String::from("<table>\n<tr><th>Rank</th><th>Position</th>");
+    if reference.is_some() {
+        table += "<th>Reference position</th>";
+    }
+    if annotation.is_some() {
+        table += "<th>Feature</th>";
+    }
+    table += "<th>Shannon entropy</th><th>Fraction notations</th><th>Major allele</th>\
+              <th>Minor allele</th><th>MAF</th></tr>\n";
+    ranked.iter().take(cli.report_top).enumerate().for_each(|(rank, (idx, entropy))| {
+        let char_map = &map_vec[*idx];
+        let alleles = ranked_alleles(char_map);
+        let total: f64 = alleles.iter().map(|(_, count)| count).sum();
+        let share: f64 = notation_counts(char_map, notations).iter().sum();
+        let allele = |x: usize| alleles.get(x).map_or(String::from("NA"), |(x, _)| x.to_string());
+        let maf = alleles.get(1).map_or(0.0, |(_, count)| count/total);
+        table += &format!("<tr><td class=\"num\">{}</td><td class=\"num\">{}</td>", rank + 1, idx + 1);
+        if let Some(reference) = reference {
+            let position = reference.reference_position(*idx);
+            table += &format!("<td class=\"num\">{}</td>", position.map_or(String::from("NA"), |x| x.to_string()));
+            if let Some(annotation) = annotation {
+                let feature = annotation.values(position).map_or(String::from("NA"), |x| x.0);
+                table += &format!("<td>{}</td>", escape_markup(&feature));
+            }
+        }
+        table += &format!("<td class=\"num\">{entropy:.4}</td><td class=\"num\">{:.4}</td><td>{}</td>\
+                           <td>{}</td><td class=\"num\">{maf:.4}</td></tr>\n",
+                          share/genome_count, allele(0), allele(1));
+    });
+    table += "</table>\n";
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    #[test]
+    fn report_sections() {
+        let dir = TestDir::new("report");
+        let records = [("s1", "ACGTA"), ("s2", "ACGAA"), ("s3", "ACNAA"), ("s4", "ATGT-")];
+        let path = dir.alignment("a.fasta", &records);
+        let sequences: Vec<&str> = records.iter().map(|(_, x)| *x).collect();
+        let (map_vec, genome_count) = counts(&sequences, &Mode::Standard);
+        let cli = entropy_args(&path, &["--report", "--report-top", "1"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        report_html(&map_vec, genome_count, 0, &mut file, &cli, None, None).unwrap();
+        let html = dir.read("a.fasta_shannon_entropy_output_report.html");
+        assert!(html.starts_with("<!DOCTYPE html>") && html.ends_with("</html>\n"));
+        let sections: Vec<&str> = html.match_indices("<h2>").map(|(idx, _)| &html[idx + 4..])
+                                      .map(|x| &x[..x.find("</h2>").unwrap()])
+                                      .collect();
+        assert_eq!(sections, ["Alignment statistics", "Entropy profile", "Top 1 positions by entropy",
+                              "Run parameters"]);
+        assert!(html.contains("<tr><th>Sequences</th><td class=\"num\">4</td></tr>"));
+        // positions 3 and 5 hold 3 of 4 notations, below the threshold
+        assert!(html.contains("<tr><th>Valid positions</th><td class=\"num\">3 (threshold 0.8)</td></tr>"));
+        assert!(html.contains("<tr><th>Gap % (- and .)</th><td class=\"num\">5.000</td></tr>"));
+        assert!(html.contains("<tr><th>N %</th><td class=\"num\">5.000</td></tr>"));
+        assert_eq!(html.matches("<svg").count(), 1);
+        // position 4 has the highest entropy, the second ranked position 2 is cut off
+        assert!(html.contains("<tr><td class=\"num\">1</td><td class=\"num\">4</td><td class=\"num\">1.0000</td>\
+                               <td class=\"num\">1.0000</td><td>A</td><td>T</td><td class=\"num\">0.5000</td></tr>"));
+        assert!(!html.contains("<td class=\"num\">2</td><td class=\"num\">2</td>"));
+        assert!(html.contains("<tr><th>Mode</th><td>Standard</td></tr>"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn constant_alignment_has_no_top_positions() {
+        let dir = TestDir::new("report_constant");
+        let path = dir.alignment("a.fasta", &[("s1", "ACGT"), ("s2", "ACGT")]);
+        let (map_vec, genome_count) = counts(&["ACGT", "ACGT"], &Mode::Standard);
+        let cli = entropy_args(&path, &["--report"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        report_html(&map_vec, genome_count, 0, &mut file, &cli, None, None).unwrap();
+        let html = dir.read("a.fasta_shannon_entropy_output_report.html");
+        assert!(html.contains("<p>No variable valid positions.</p>"));
+    }
+}
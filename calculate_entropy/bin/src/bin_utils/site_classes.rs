@@ -0,0 +1,133 @@
+/*
+    Classify alignment positions for phylogenetics
+        Alleles are A, T, G and C, gaps and ambiguity codes are ignored
+        Constant: at most one allele
+        Singleton: variable, but at most one allele present in two or more sequences
+        Parsimony informative: at least two alleles each present in two or more sequences
+    Write a summary of the classes
+    Optionally write the alignment of the parsimony informative positions
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{create_output, notation_counts, output_name};
+use super::get_args::EntropyArgs;
+
+const ALLELES: &str = "ATGC";
+const FASTA_LINE_LENGTH: usize = 60;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SiteClass {
+    Constant,
+    Singleton,
+    ParsimonyInformative,
+}
+
+impl SiteClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SiteClass::Constant => "Constant",
+            SiteClass::Singleton => "Singleton",
+            SiteClass::ParsimonyInformative => "Parsimony_informative",
+        }
+    }
+}
+
+pub fn site_class(char_map: &HashMap<char, f64>) -> SiteClass {
+    let allele_counts = notation_counts(char_map, ALLELES);
+    let observed = allele_counts.iter().filter(|&&x| x > 0.0).count();
+    let shared = allele_counts.iter().filter(|&&x| x >= 2.0).count();
+    if observed <= 1 {
+        SiteClass::Constant
+    } else if shared >= 2 {
+        SiteClass::ParsimonyInformative
+    } else {
+        SiteClass::Singleton
+    }
+}
+
+pub fn report_site_classes(map_vec: &[HashMap<char, f64>], file: &mut FileBufferHelper,
+                           cli: &EntropyArgs) -> Result<(), String> {
+    let classes: Vec<SiteClass> = map_vec.iter().map(site_class).collect();
+    let out_file_name = output_name(file, &cli.output, "site_classes");
+    info!("Site class summary output file: {}", out_file_name);
+    let mut summary_writer = BufWriter::new(create_output(&out_file_name, &cli.output)?);
+    writeln!(summary_writer, "Site_class{delim}Positions", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    [SiteClass::Constant, SiteClass::Singleton, SiteClass::ParsimonyInformative].iter()
+        .for_each(|class| {
+            let count = classes.iter().filter(|x| *x == class).count();
+            info!("{} positions: {}", class.name(), count);
+            writeln!(summary_writer, "{}{}{}", class.name(), cli.output.delimiter, count)
+                .expect("Unable to write to file");
+        });
+    if cli.informative_alignment {
+        let informative: Vec<usize> = classes.iter().enumerate()
+                                        .filter(|(_, x)| **x == SiteClass::ParsimonyInformative)
+                                        .map(|(idx, _)| idx)
+                                        .collect();
+        write_informative_alignment(&informative, file, cli)?;
+    }
+    Ok(())
+}
+
+fn write_informative_alignment(informative: &[usize], file: &mut FileBufferHelper,
+                               cli: &EntropyArgs) -> Result<(), String> {
+    let out_file_name = output_name(file, &cli.output, "informative.fasta");
+    info!("Parsimony informative alignment output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut alignment_writer = BufWriter::new(out_file);
+    file.buffer_reset();
+    while let Some((header, genome)) = file.next_record() {
+        let genome = genome.as_bytes();
+        let sites: Vec<u8> = informative.iter()
+                                .map(|&idx| *genome.get(idx).unwrap_or(&b'-'))
+                                .collect();
+        writeln!(alignment_writer, ">{header}").expect("Unable to write to file");
+        sites.chunks(FASTA_LINE_LENGTH).for_each(|line| {
+            writeln!(alignment_writer, "{}", String::from_utf8_lossy(line))
+                .expect("Unable to write to file");
+        });
+    }
+    file.buffer_reset();
+    info!("{} parsimony informative positions written", informative.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    const ALIGNMENT: [(&str, &str); 6] = [("s1", "AAAAR"), ("s2", "AAAAR"), ("s3", "AACCY"), ("s4", "AAGCY"),
+                                          ("s5", "NATGA"), ("s6", "-GATA")];
+
+    #[test]
+    fn classes_of_known_columns() {
+        let sequences: Vec<&str> = ALIGNMENT.iter().map(|(_, x)| *x).collect();
+        let (map_vec, _) = counts(&sequences, &Mode::All);
+        let classes: Vec<&str> = map_vec.iter().map(|x| site_class(x).name()).collect();
+        // gaps, N and ambiguity codes are not alleles
+        // the four bases with two of them shared make the fourth column parsimony informative
+        assert_eq!(classes, ["Constant", "Singleton", "Singleton", "Parsimony_informative", "Constant"]);
+    }
+
+    #[test]
+    fn summary_and_informative_alignment() {
+        let dir = TestDir::new("site_classes");
+        let path = dir.alignment("a.fasta", &ALIGNMENT);
+        let sequences: Vec<&str> = ALIGNMENT.iter().map(|(_, x)| *x).collect();
+        let (map_vec, _) = counts(&sequences, &Mode::All);
+        let cli = entropy_args(&path, &["--site-classes", "--informative-alignment"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        report_site_classes(&map_vec, &mut file, &cli).unwrap();
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_site_classes"),
+                   "Site_class,Positions\nConstant,2\nSingleton,2\nParsimony_informative,1\n");
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_informative.fasta"),
+                   ">s1\nA\n>s2\nA\n>s3\nC\n>s4\nC\n>s5\nG\n>s6\nT\n");
+    }
+}
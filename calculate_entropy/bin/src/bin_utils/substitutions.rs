@@ -0,0 +1,163 @@
+/*
+    Transitions and transversions at every position
+        Base of the position: reference base, or the major allele of A, T, G and C
+        Substitutions are the sequences carrying another of A, T, G and C
+            transitions: A <-> G, C <-> T
+            transversions: all others
+        Per position: transitions, transversions and Ts/Tv
+            NA at positions whose base is a gap or ambiguous, or without A, T, G and C
+        Alignment wide: counts of the 12 substitution classes (mutation spectrum)
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::alleles::ranked_alleles;
+use super::calculate_entropy::{create_output, notation_counts, output_name};
+use super::get_args::{EntropyArgs, SubstitutionBase};
+use super::reference::Reference;
+
+const BASES: &str = "ATGC";
+
+pub fn is_transition(from: char, to: char) -> bool {
+    matches!((from, to), ('A', 'G') | ('G', 'A') | ('C', 'T') | ('T', 'C'))
+}
+
+pub fn report_substitutions(map_vec: &[HashMap<char, f64>], file: &FileBufferHelper, cli: &EntropyArgs,
+                            reference: Option<&Reference>) -> Result<(), String> {
+    let out_file_name = output_name(file, &cli.output, "substitutions");
+    info!("Substitutions output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut substitution_writer = BufWriter::new(out_file);
+    writeln!(substitution_writer, "Position{delim}Base{delim}Substitutions{delim}Transitions{delim}\
+                                   Transversions{delim}Ts_Tv", delim=cli.output.delimiter)
+        .expect("Unable to write to file");
+    // substitution counts, from base -> to base
+    let mut spectrum: HashMap<(char, char), f64> = HashMap::new();
+    let mut skipped = 0usize;
+    map_vec.iter().enumerate().for_each(|(idx, char_map)| {
+        let base = match (cli.substitution_base, reference) {
+            (SubstitutionBase::Reference, Some(reference)) => reference.sequence.as_bytes()
+                                                                    .get(idx)
+                                                                    .map(|x| *x as char),
+            _ => ranked_alleles(char_map).first().map(|(x, _)| *x)
+        };
+        let base = match base {
+            Some(x) if BASES.contains(x) => x,
+            x => {
+                skipped += 1;
+                writeln!(substitution_writer, "{pos}{delim}{base}{delim}NA{delim}NA{delim}NA{delim}NA",
+                         pos=idx + 1, base=x.map_or(String::from("NA"), |x| x.to_string()),
+                         delim=cli.output.delimiter).expect("Unable to write to file");
+                return
+            }
+        };
+        let mut transitions = 0.0f64;
+        let mut transversions = 0.0f64;
+        BASES.chars().zip(notation_counts(char_map, BASES))
+            .filter(|(x, count)| *x != base && *count > 0.0)
+            .for_each(|(x, count)| {
+                *spectrum.entry((base, x)).or_insert(0.0) += count;
+                if is_transition(base, x) {
+                    transitions += count;
+                } else {
+                    transversions += count;
+                }
+            });
+        let ts_tv = if transversions > 0.0 {
+            (transitions/transversions).to_string()
+        } else {
+            String::from("NA")
+        };
+        writeln!(substitution_writer, "{pos}{delim}{base}{delim}{substitutions}{delim}\
+                                       {transitions}{delim}{transversions}{delim}{ts_tv}",
+                 pos=idx + 1, substitutions=transitions + transversions, delim=cli.output.delimiter)
+            .expect("Unable to write to file");
+    });
+    if skipped > 0 {
+        info!("{} positions without a base of A, T, G or C have no substitutions", skipped);
+    }
+    write_spectrum(&spectrum, file, cli)
+}
+
+fn write_spectrum(spectrum: &HashMap<(char, char), f64>, file: &FileBufferHelper,
+                  cli: &EntropyArgs) -> Result<(), String> {
+    let out_file_name = output_name(file, &cli.output, "mutation_spectrum");
+    info!("Mutation spectrum output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut spectrum_writer = BufWriter::new(out_file);
+    writeln!(spectrum_writer, "Substitution{delim}Type{delim}Count{delim}Fraction",
+             delim=cli.output.delimiter).expect("Unable to write to file");
+    let total: f64 = spectrum.values().sum();
+    let transitions: f64 = spectrum.iter()
+                            .filter(|((from, to), _)| is_transition(*from, *to))
+                            .map(|(_, count)| count)
+                            .sum();
+    if total > transitions {
+        info!("Alignment wide Ts/Tv: {}", transitions/(total - transitions));
+    } else {
+        info!("Alignment wide Ts/Tv: NA, no transversions");
+    }
+    BASES.chars().for_each(|from| {
+        BASES.chars().filter(|to| *to != from).for_each(|to| {
+            let count = spectrum.get(&(from, to)).copied().unwrap_or(0.0);
+            let substitution_type = if is_transition(from, to) { "Transition" } else { "Transversion" };
+            let fraction = if total > 0.0 { count/total } else { 0.0 };
+            writeln!(spectrum_writer, "{from}>{to}{delim}{substitution_type}{delim}{count}{delim}\
+                                       {fraction}", delim=cli.output.delimiter)
+                .expect("Unable to write to file");
+        });
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::reference::load_reference;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    #[test]
+    fn substitutions_from_the_reference() {
+        let dir = TestDir::new("substitutions");
+        let path = dir.alignment("a.fasta", &[("ref", "ACGTAC-"), ("s2", "GTGATCA")]);
+        let (map_vec, _) = counts(&["ACGTAC-", "GTGATCA"], &Mode::Standard);
+        let cli = entropy_args(&path, &["--substitutions", "--substitution-base", "reference"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        let reference = load_reference(&mut file, None).unwrap();
+        report_substitutions(&map_vec, &file, &cli, Some(&reference)).unwrap();
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_substitutions"),
+                   "Position,Base,Substitutions,Transitions,Transversions,Ts_Tv\n\
+                    1,A,1,1,0,NA\n\
+                    2,C,1,1,0,NA\n\
+                    3,G,0,0,0,NA\n\
+                    4,T,1,0,1,0\n\
+                    5,A,1,0,1,0\n\
+                    6,C,0,0,0,NA\n\
+                    7,-,NA,NA,NA,NA\n");
+        // substitutions are counted from the reference base to the other base only
+        let spectrum = dir.read("a.fasta_shannon_entropy_output_mutation_spectrum");
+        let counts: HashMap<&str, &str> = spectrum.lines().skip(1)
+                                            .map(|x| {
+                                                let fields: Vec<&str> = x.split(',').collect();
+                                                (fields[0], fields[2])
+                                            })
+                                            .collect();
+        assert_eq!(counts.len(), 12);
+        assert_eq!((counts["A>G"], counts["G>A"]), ("1", "0"));
+        assert_eq!((counts["C>T"], counts["T>C"]), ("1", "0"));
+        assert_eq!((counts["T>A"], counts["A>T"]), ("1", "1"));
+        assert_eq!(counts.values().filter(|x| **x != "0").count(), 4);
+        assert!(spectrum.contains("A>G,Transition,1,0.25\n"));
+        assert!(spectrum.contains("A>T,Transversion,1,0.25\n"));
+    }
+
+    #[test]
+    fn transitions_are_purine_or_pyrimidine_changes() {
+        assert!(is_transition('A', 'G') && is_transition('T', 'C'));
+        assert!(!is_transition('A', 'C') && !is_transition('G', 'T') && !is_transition('A', 'A'));
+    }
+}
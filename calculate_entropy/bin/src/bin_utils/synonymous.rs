@@ -0,0 +1,296 @@
+/*
+    Synonymous and non-synonymous variability at coding positions
+        For every codon of the reference
+            translate the reference codon, stop codons are skipped
+            for each codon position (Nei-Gojobori)
+                synonymous sites: share of the three possible changes that keep the amino acid
+                non-synonymous sites: share of the three possible changes to another amino acid
+                    changes to a stop codon are neither, the sites of a codon may sum to less than 3
+                observed alternative bases from the column counts
+                    mutate the reference codon, translate
+                    same amino acid: synonymous, stop codon: left out, else non-synonymous
+                    weighted by the number of sequences carrying the base
+        Per coding sequence
+            synonymous and non-synonymous sites and differences (Nei-Gojobori)
+            pS, pN and the Jukes-Cantor corrected dN/dS
+                NA without synonymous or non-synonymous sites
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::{debug, info};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::annotation::Annotation;
+use super::calculate_entropy::{create_output, output_name};
+use super::codon::codon_sites;
+use super::genetic_code::{complement, GeneticCode};
+use super::get_args::EntropyArgs;
+use super::reference::Reference;
+
+const BASES: [char; 4] = ['A', 'T', 'G', 'C'];
+const STOP: char = '*';
+
+struct PositionChanges {
+    synonymous_sites: f64,
+    non_synonymous_sites: f64,
+    synonymous: f64,
+    non_synonymous: f64,
+    total: f64,
+}
+
+#[derive(Default)]
+struct CodingSummary {
+    feature: String,
+    codons: usize,
+    synonymous_sites: f64,
+    non_synonymous_sites: f64,
+    synonymous_differences: f64,
+    non_synonymous_differences: f64,
+}
+
+pub fn report_synonymous(map_vec: &[HashMap<char, f64>], file: &FileBufferHelper, cli: &EntropyArgs,
+                         reference: &Reference, annotation: Option<&Annotation>) -> Result<(), String> {
+    let genetic_code = GeneticCode::new(cli.genetic_code)
+                                        .expect("Translation table validated by the parser");
+    let sites = codon_sites(reference, annotation, cli.frame as usize);
+    let out_file_name = output_name(file, &cli.output, "synonymous");
+    info!("Synonymous changes output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut position_writer = BufWriter::new(out_file);
+    writeln!(position_writer, "Position{delim}Reference_position{delim}Feature{delim}\
+                               Codon_number{delim}Codon_position{delim}Reference_codon{delim}\
+                               Reference_amino_acid{delim}Synonymous_sites{delim}\
+                               Non_synonymous_sites{delim}Synonymous_alleles{delim}Non_synonymous_alleles{delim}\
+                               Synonymous_frequency{delim}Non_synonymous_frequency",
+             delim=cli.output.delimiter).expect("Unable to write to file");
+
+    let mut summaries: Vec<CodingSummary> = Vec::new();
+    for site in &sites {
+        // codon numbering restarts with every coding sequence
+        if site.codon_number == 1 || summaries.is_empty() {
+            summaries.push(CodingSummary { feature: site.feature.clone(), ..Default::default() });
+        }
+        let reference_codon = site.codon(reference.sequence.as_bytes());
+        let reference_amino_acid = reference_codon.and_then(|x| genetic_code.translate(&x));
+        let (reference_codon, reference_amino_acid) = match (reference_codon, reference_amino_acid) {
+            (Some(codon), Some(amino_acid)) if amino_acid != STOP => (codon, amino_acid),
+            _ => {
+                debug!("Reference codon {} of {} is a stop codon or not translatable. Skipped.",
+                    site.codon_number, site.feature);
+                continue
+            }
+        };
+        let summary = summaries.last_mut().unwrap();
+        summary.codons += 1;
+        for codon_position in 0..3 {
+            let column = site.columns[codon_position];
+            let changes = position_changes(&map_vec[column], &reference_codon, codon_position,
+                                           reference_amino_acid, site.minus_strand, &genetic_code);
+            summary.synonymous_sites += changes.synonymous_sites;
+            summary.non_synonymous_sites += changes.non_synonymous_sites;
+            let (synonymous_frequency, non_synonymous_frequency) = if changes.total > 0.0 {
+                (changes.synonymous/changes.total, changes.non_synonymous/changes.total)
+            } else {
+                (0.0, 0.0)
+            };
+            summary.synonymous_differences += synonymous_frequency;
+            summary.non_synonymous_differences += non_synonymous_frequency;
+            writeln!(position_writer, "{pos}{delim}{ref_pos}{delim}{feature}{delim}{number}{delim}\
+                                       {codon_position}{delim}{codon}{delim}{amino_acid}{delim}\
+                                       {sites}{delim}{non_synonymous_sites}{delim}{synonymous}{delim}\
+                                       {non_synonymous}{delim}\
+                                       {synonymous_frequency}{delim}{non_synonymous_frequency}",
+                     pos=column + 1, ref_pos=site.positions[codon_position],
+                     feature=site.feature, number=site.codon_number,
+                     codon_position=codon_position + 1,
+                     codon=reference_codon.iter().collect::<String>(),
+                     amino_acid=reference_amino_acid, sites=changes.synonymous_sites,
+                     non_synonymous_sites=changes.non_synonymous_sites,
+                     synonymous=changes.synonymous, non_synonymous=changes.non_synonymous,
+                     delim=cli.output.delimiter).expect("Unable to write to file");
+        }
+    }
+    write_dnds_summary(&summaries, file, cli)
+}
+
+fn position_changes(column: &HashMap<char, f64>, reference_codon: &[char; 3], codon_position: usize,
+                    reference_amino_acid: char, minus_strand: bool,
+                    genetic_code: &GeneticCode) -> PositionChanges {
+    let translate_with = |base: char| {
+        let mut codon = *reference_codon;
+        codon[codon_position] = base;
+        genetic_code.translate(&codon)
+    };
+    // share of possible changes that are synonymous and non-synonymous, stop codons left out
+    let possible: Vec<Option<char>> = BASES.iter()
+                                        .filter(|&&x| x != reference_codon[codon_position])
+                                        .map(|&x| translate_with(x))
+                                        .collect();
+    let share = |synonymous: bool| possible.iter()
+                                    .filter(|x| x.is_some_and(|y| y != STOP &&
+                                                                  (y == reference_amino_acid) == synonymous))
+                                    .count() as f64 / 3.0;
+    let mut changes = PositionChanges {
+        synonymous_sites: share(true),
+        non_synonymous_sites: share(false),
+        synonymous: 0.0,
+        non_synonymous: 0.0,
+        total: 0.0,
+    };
+    // observed bases are on the reference strand
+    // sequences with a stop codon are left out
+    BASES.iter().for_each(|&base| {
+        let count = column.get(&base).copied().unwrap_or(0.0);
+        let transcript_base = if minus_strand { complement(base) } else { base };
+        if transcript_base != reference_codon[codon_position] {
+            match translate_with(transcript_base) {
+                Some(STOP) => return,
+                Some(x) if x == reference_amino_acid => changes.synonymous += count,
+                _ => changes.non_synonymous += count
+            }
+        }
+        changes.total += count;
+    });
+    changes
+}
+
+fn proportion(differences: f64, sites: f64) -> Option<f64> {
+    // differences per site, undefined without sites
+    if sites > 0.0 {
+        Some(differences/sites)
+    } else {
+        None
+    }
+}
+
+fn jukes_cantor(p: f64) -> Option<f64> {
+    // corrected distance, undefined for p >= 0.75
+    if p == 0.0 {
+        Some(0.0)
+    } else if p < 0.75 {
+        Some(-0.75 * (1.0 - 4.0 * p / 3.0).ln())
+    } else {
+        None
+    }
+}
+
+fn write_dnds_summary(summaries: &[CodingSummary], file: &FileBufferHelper,
+                      cli: &EntropyArgs) -> Result<(), String> {
+    let out_file_name = output_name(file, &cli.output, "dnds");
+    info!("dN/dS summary output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut summary_writer = BufWriter::new(out_file);
+    writeln!(summary_writer, "Feature{delim}Codons{delim}Synonymous_sites{delim}\
+                              Non_synonymous_sites{delim}Synonymous_differences{delim}\
+                              Non_synonymous_differences{delim}pS{delim}pN{delim}pN_pS{delim}\
+                              dN_dS", delim=cli.output.delimiter).expect("Unable to write to file");
+    let na = || String::from("NA");
+    summaries.iter().for_each(|summary| {
+        let p_s = proportion(summary.synonymous_differences, summary.synonymous_sites);
+        let p_n = proportion(summary.non_synonymous_differences, summary.non_synonymous_sites);
+        let pn_ps = match (p_n, p_s) {
+            (Some(p_n), Some(p_s)) if p_s > 0.0 => (p_n/p_s).to_string(),
+            _ => na()
+        };
+        let dn_ds = match (p_n.and_then(jukes_cantor), p_s.and_then(jukes_cantor)) {
+            (Some(d_n), Some(d_s)) if d_s > 0.0 => (d_n/d_s).to_string(),
+            _ => na()
+        };
+        let (p_s, p_n) = (p_s.map_or(na(), |x| x.to_string()), p_n.map_or(na(), |x| x.to_string()));
+        writeln!(summary_writer, "{feature}{delim}{codons}{delim}{s_sites}{delim}{n_sites}{delim}\
+                                  {s_diff}{delim}{n_diff}{delim}{p_s}{delim}{p_n}{delim}\
+                                  {pn_ps}{delim}{dn_ds}",
+                 feature=summary.feature, codons=summary.codons,
+                 s_sites=summary.synonymous_sites, n_sites=summary.non_synonymous_sites,
+                 s_diff=summary.synonymous_differences, n_diff=summary.non_synonymous_differences,
+                 delim=cli.output.delimiter).expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::reference::load_reference;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    #[test]
+    fn jukes_cantor_of_no_differences_is_zero() {
+        let distance = jukes_cantor(0.0).unwrap();
+        assert_eq!(distance, 0.0);
+        assert!(distance.is_sign_positive());
+    }
+
+    #[test]
+    fn jukes_cantor_known_values() {
+        // d = -3/4 ln(1 - 4p/3)
+        assert!((jukes_cantor(0.1).unwrap() - 0.107326).abs() < 1e-6);
+        assert!((jukes_cantor(0.5).unwrap() - 0.823959).abs() < 1e-6);
+        assert_eq!(jukes_cantor(0.75), None);
+        assert_eq!(jukes_cantor(0.9), None);
+    }
+
+    fn column(counts: &[(char, f64)]) -> HashMap<char, f64> {
+        counts.iter().copied().collect()
+    }
+
+    #[test]
+    fn changes_of_a_known_codon() {
+        let code = GeneticCode::new(1).unwrap();
+        let codon = ['T', 'T', 'A'];
+        // TTA (L) -> CTA (L) synonymous, ATA (I) and GTA (V) non-synonymous
+        let changes = position_changes(&column(&[('T', 3.0), ('C', 1.0)]), &codon, 0, 'L', false, &code);
+        assert_eq!((changes.synonymous_sites, changes.non_synonymous_sites), (1.0/3.0, 2.0/3.0));
+        assert_eq!((changes.synonymous, changes.non_synonymous, changes.total), (1.0, 0.0, 4.0));
+        // TTA -> TCA (S) non-synonymous, TAA and TGA are stop codons and left out
+        let changes = position_changes(&column(&[('T', 2.0), ('C', 1.0), ('A', 1.0)]), &codon, 1, 'L', false,
+                                       &code);
+        assert_eq!((changes.synonymous_sites, changes.non_synonymous_sites), (0.0, 1.0/3.0));
+        assert_eq!((changes.synonymous, changes.non_synonymous, changes.total), (0.0, 1.0, 3.0));
+        // TTA -> TTG (L) synonymous, TTT and TTC (F) non-synonymous
+        let changes = position_changes(&column(&[('A', 1.0), ('G', 1.0)]), &codon, 2, 'L', false, &code);
+        assert_eq!((changes.synonymous_sites, changes.non_synonymous_sites), (1.0/3.0, 2.0/3.0));
+        assert_eq!((changes.synonymous, changes.non_synonymous, changes.total), (1.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn minus_strand_bases_are_complemented() {
+        let code = GeneticCode::new(1).unwrap();
+        // G on the reference strand is C in the transcript: TTA -> CTA
+        let changes = position_changes(&column(&[('A', 1.0), ('G', 1.0)]), &['T', 'T', 'A'], 0, 'L', true,
+                                       &code);
+        assert_eq!((changes.synonymous, changes.non_synonymous, changes.total), (1.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn stop_codons_are_not_sites() {
+        let dir = TestDir::new("synonymous");
+        let path = dir.alignment("a.fasta", &[("s1", "ATGTTATAA"), ("s2", "ATGCTATAG"), ("s3", "ATGTCATAA")]);
+        let cli = entropy_args(&path, &["--synonymous"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        let reference = load_reference(&mut file, None).unwrap();
+        let (map_vec, _) = counts(&["ATGTTATAA", "ATGCTATAG", "ATGTCATAA"], &Mode::Standard);
+        report_synonymous(&map_vec, &file, &cli, &reference, None).unwrap();
+        // the stop codon TAA is skipped, TAA -> TAG is not synonymous
+        let positions = dir.read("a.fasta_shannon_entropy_output_synonymous");
+        assert_eq!(positions.lines().count(), 7);
+        assert!(positions.lines().all(|x| !x.contains("TAA")));
+        // ATG: 0 synonymous and 3 non-synonymous sites, TTA: 2/3 and 5/3
+        let summary = dir.read("a.fasta_shannon_entropy_output_dnds");
+        let row: Vec<&str> = summary.lines().nth(1).unwrap().split(',').collect();
+        assert_eq!(row[..2], ["Frame_1", "2"]);
+        assert!((row[2].parse::<f64>().unwrap() - 2.0/3.0).abs() < 1e-12);
+        assert!((row[3].parse::<f64>().unwrap() - 14.0/3.0).abs() < 1e-12);
+        // one of three sequences differs synonymously at TTA position 1, one non-synonymously at position 2
+        assert!((row[4].parse::<f64>().unwrap() - 1.0/3.0).abs() < 1e-12);
+        assert!((row[5].parse::<f64>().unwrap() - 1.0/3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn proportion_without_sites_is_undefined() {
+        assert_eq!(proportion(0.0, 0.0), None);
+        assert_eq!(proportion(1.0, 4.0), Some(0.25));
+    }
+}
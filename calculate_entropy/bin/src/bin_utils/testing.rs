@@ -0,0 +1,90 @@
+/*
+    Helpers of the unit tests
+        temporary directory removed at the end of the test
+        alignments written in fasta format, every sequence wrapped over two lines as check_fasta expects
+        position wise counts of an alignment as process_genomes returns them
+        entropy subcommand options of an alignment, in 'Standard' mode with one thread
+*/
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use clap::Parser;
+use check_fasta::check_fasta;
+use super::calculate_entropy::{initialise_structs, process_genomes};
+use super::get_args::{Cli, Command, EntropyArgs, Mode};
+
+// tests run in parallel, every directory is numbered apart
+static DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestDir(pub PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        let path = std::env::temp_dir().join(format!("calculate_entropy_{}_{}_{}", std::process::id(),
+                                                     DIRECTORIES.fetch_add(1, Ordering::Relaxed), name));
+        remove_dir_all(&path).ok();
+        create_dir_all(&path).expect("Unable to create the test directory");
+        TestDir(path)
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    // write the records as a fasta alignment, every sequence over two lines
+    pub fn alignment(&self, name: &str, records: &[(&str, &str)]) -> PathBuf {
+        let content: String = records.iter().map(|(header, sequence)| {
+            let (first, second) = sequence.split_at(sequence.len().div_ceil(2));
+            format!(">{header}\n{first}\n{second}\n")
+        }).collect();
+        self.file(name, &content)
+    }
+
+    pub fn file(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.path(name);
+        write(&path, content).expect("Unable to write the test file");
+        path
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        read_to_string(self.path(name)).expect("Unable to read the test output")
+    }
+
+    // names of the files in the directory, sorted
+    pub fn files(&self) -> Vec<String> {
+        let mut files: Vec<String> = read_dir(&self.0).expect("Unable to read the test directory")
+            .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        remove_dir_all(&self.0).ok();
+    }
+}
+
+// position wise counts and number of the sequences, counted from an alignment file
+pub fn counts(sequences: &[&str], mode: &Mode) -> (Vec<HashMap<char, f64>>, f64) {
+    let dir = TestDir::new("counts");
+    let headers: Vec<String> = (1..=sequences.len()).map(|x| format!("s{x}")).collect();
+    let records: Vec<(&str, &str)> = headers.iter().map(|x| x.as_str()).zip(sequences.iter().copied()).collect();
+    let path = dir.alignment("counts.fasta", &records);
+    let mut file = check_fasta(&path, true).expect("Invalid test alignment");
+    let count_vec = initialise_structs(&mut file, mode);
+    file.buffer_reset();
+    let (map_vec, genome_count, _) = process_genomes(count_vec, &mut file, 1, None);
+    (map_vec, genome_count)
+}
+
+pub fn entropy_args(input: &Path, options: &[&str]) -> EntropyArgs {
+    let args = ["calculate_entropy", "entropy", "-i", input.to_str().unwrap(), "-m", "standard", "-n", "1"];
+    match Cli::parse_from(args.iter().chain(options)).command {
+        Command::Entropy(x) => *x,
+        _ => unreachable!()
+    }
+}
@@ -0,0 +1,275 @@
+/*
+    Shannon entropy per collection date bin
+        Collection date of a sequence (YYYY-MM-DD)
+            metadata date column when given
+            else the last field of the fasta header holding a date
+                fields are split on the ID separator, "/" and whitespace
+                e.g. hCoV-19/country/id/2021/EPI_ISL_x/2021-03-04
+                     hCoV-19/country/id/2021|EPI_ISL_x|2021-03-04
+        Bin the dates by ISO week or by month
+        Count the notations of every bin in one pass over the alignment
+            sequences without a complete date are logged and counted
+    Write
+        positions x time bins matrix of entropy
+            NA for positions below the threshold, and for every position of a bin below the minimum
+        summary of the bins, bins with fewer sequences than the minimum are flagged
+*/
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::{info, warn};
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{create_output, output_name, valid_entropies};
+use super::get_args::{EntropyArgs, TimeBin};
+use super::groups::grouped_counts;
+use super::metadata::{read_metadata, sequence_id, Metadata};
+
+pub fn report_time_bins(map_vec: &[HashMap<char, f64>], file: &mut FileBufferHelper,
+                        cli: &EntropyArgs) -> Result<(), String> {
+    let time_bin = match cli.time_bin {
+        Some(x) => x,
+        None => return Ok(())
+    };
+    // metadata date column, when requested
+    let metadata_dates: Option<(Metadata, usize)> = match (&cli.metadata, &cli.date_column) {
+        (Some(path), Some(date_column)) => {
+            let metadata = read_metadata(path, cli.metadata_id.as_ref())?;
+            let idx = metadata.column_index(date_column)
+                .ok_or(format!("Date column {} not found in metadata {:?}", date_column, path))?;
+            Some((metadata, idx))
+        },
+        _ => None
+    };
+    // first and last bin observed
+    let bin_range: Cell<Option<(i64, i64)>> = Cell::new(None);
+    let (bins, undated) = grouped_counts(file, map_vec, cli, |header| {
+        let date = match &metadata_dates {
+            Some((metadata, idx)) => sequence_id(header, cli)
+                                        .and_then(|id| metadata.value(id, *idx))
+                                        .and_then(parse_date),
+            None => header_date(header, cli.id_separator)
+        }?;
+        let idx = bin_index(date, time_bin);
+        bin_range.set(Some(bin_range.get().map_or((idx, idx), |(first, last)|
+                                                    (first.min(idx), last.max(idx)))));
+        Some(bin_label(idx, time_bin))
+    });
+    info!("{} sequences without a collection date", undated);
+    let (first, last) = bin_range.get().ok_or(format!("No collection dates found in {:?}", file.path))?;
+    // every bin between the first and the last date, including empty bins
+    // entropy of too few sequences is not reported
+    let labels: Vec<String> = (first..=last).map(|idx| bin_label(idx, time_bin)).collect();
+    let entropies: Vec<Option<Vec<Option<f64>>>> = labels.iter()
+        .map(|label| bins.get(label)
+                        .filter(|(_, genome_count)| *genome_count >= cli.min_bin_size as f64)
+                        .map(|(counts, genome_count)| valid_entropies(counts, *genome_count, &cli.counting)))
+        .collect();
+
+    let out_file_name = output_name(file, &cli.output, "time_bins");
+    info!("Time bin summary output file: {}", out_file_name);
+    let mut summary_writer = BufWriter::new(create_output(&out_file_name, &cli.output)?);
+    writeln!(summary_writer, "Bin{delim}Start{delim}End{delim}Sequences{delim}Status",
+             delim=cli.output.delimiter).expect("Unable to write to file");
+    (first..=last).zip(labels.iter()).for_each(|(idx, label)| {
+        let sequences = bins.get(label).map_or(0.0, |(_, genome_count)| *genome_count);
+        let (start, end) = bin_dates(idx, time_bin);
+        let status = if sequences < cli.min_bin_size as f64 {
+            warn!("Time bin {} holds {} sequences, below the minimum of {}",
+                  label, sequences, cli.min_bin_size);
+            "Low_count"
+        } else {
+            "OK"
+        };
+        writeln!(summary_writer, "{label}{delim}{start}{delim}{end}{delim}{sequences}{delim}{status}",
+                 start=format_date(start), end=format_date(end), delim=cli.output.delimiter)
+            .expect("Unable to write to file");
+    });
+
+    let out_file_name = output_name(file, &cli.output, "time_entropy");
+    info!("Time binned entropy output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut matrix_writer = BufWriter::new(out_file);
+    let headers = labels.iter().map(|x| format!("{}{x}", cli.output.delimiter)).collect::<String>();
+    writeln!(matrix_writer, "Position{headers}").expect("Unable to write to file");
+    (0..map_vec.len()).for_each(|pos| {
+        let fields = entropies.iter()
+            .map(|bin| match bin.as_ref().and_then(|x| x[pos]) {
+                Some(entropy) => format!("{}{entropy}", cli.output.delimiter),
+                None => format!("{}NA", cli.output.delimiter)
+            })
+            .collect::<String>();
+        writeln!(matrix_writer, "{}{fields}", pos + 1).expect("Unable to write to file");
+    });
+    Ok(())
+}
+
+// last complete date among the fields of a fasta header
+pub fn header_date(header: &str, id_separator: char) -> Option<(i64, u32, u32)> {
+    header.rsplit(|x: char| x == id_separator || x == '/' || x.is_whitespace())
+        .find_map(parse_date)
+}
+
+// (year, month, day) of a YYYY-MM-DD date, None for partial or invalid dates
+pub fn parse_date(date: &str) -> Option<(i64, u32, u32)> {
+    let mut parts = date.trim().splitn(3, '-');
+    let year = parts.next().filter(|x| x.len() == 4)?.parse::<i64>().ok()?;
+    let month = parts.next().filter(|x| x.len() == 2)?.parse::<u32>().ok()?;
+    let day = parts.next().filter(|x| x.len() == 2)?.parse::<u32>().ok()?;
+    if (1..=12).contains(&month) && day >= 1 &&
+        civil_from_days(days_from_civil(year, month, day)) == (year, month, day) {
+        Some((year, month, day))
+    } else {
+        None
+    }
+}
+
+pub fn format_date((year, month, day): (i64, u32, u32)) -> String {
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+// days since 1970-01-01 of a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let shifted_month = (month as i64 + 9) % 12;
+    let day_of_year = (153 * shifted_month + 2)/5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era/4 - year_of_era/100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096)/365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era/4 - year_of_era/100);
+    let shifted_month = (5 * day_of_year + 2)/153;
+    let day = (day_of_year - (153 * shifted_month + 2)/5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// consecutive bins have consecutive indices
+// week: index of the week starting on Monday, month: months since year 0
+fn bin_index((year, month, day): (i64, u32, u32), time_bin: TimeBin) -> i64 {
+    match time_bin {
+        // 1970-01-01 was a Thursday, weeks start on 1969-12-29
+        TimeBin::Week => (days_from_civil(year, month, day) + 3).div_euclid(7),
+        TimeBin::Month => year * 12 + month as i64 - 1
+    }
+}
+
+// first and last day of a bin
+fn bin_dates(idx: i64, time_bin: TimeBin) -> ((i64, u32, u32), (i64, u32, u32)) {
+    match time_bin {
+        TimeBin::Week => {
+            let monday = idx * 7 - 3;
+            (civil_from_days(monday), civil_from_days(monday + 6))
+        },
+        TimeBin::Month => {
+            let (year, month) = (idx.div_euclid(12), (idx.rem_euclid(12) + 1) as u32);
+            let next = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+            ((year, month, 1), civil_from_days(days_from_civil(next.0, next.1, 1) - 1))
+        }
+    }
+}
+
+// ISO 8601 week (2021-W09) or month (2021-03) of a bin
+fn bin_label(idx: i64, time_bin: TimeBin) -> String {
+    match time_bin {
+        TimeBin::Week => {
+            // the ISO year of a week is the year of its Thursday
+            let thursday = idx * 7;
+            let (year, _, _) = civil_from_days(thursday);
+            let week = (thursday - days_from_civil(year, 1, 1))/7 + 1;
+            format!("{year:04}-W{week:02}")
+        },
+        TimeBin::Month => format!("{:04}-{:02}", idx.div_euclid(12), idx.rem_euclid(12) + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    #[test]
+    fn complete_dates_are_parsed() {
+        assert_eq!(parse_date("2021-03-04"), Some((2021, 3, 4)));
+        assert_eq!(parse_date(" 2020-02-29 "), Some((2020, 2, 29)));
+    }
+
+    #[test]
+    fn partial_and_invalid_dates_are_rejected() {
+        ["2021", "2021-03", "2021-3-4", "2021-02-29", "2021-13-01", "2021-04-31", "21-03-04", "EPI_ISL_1"]
+            .iter()
+            .for_each(|x| assert_eq!(parse_date(x), None, "{x}"));
+    }
+
+    #[test]
+    fn header_dates_are_found_in_any_field() {
+        let date = Some((2021, 3, 4));
+        assert_eq!(header_date("hCoV-19/England/MILK-1/2021/EPI_ISL_1/2021-03-04", '|'), date);
+        assert_eq!(header_date("hCoV-19/England/MILK-1/2021|EPI_ISL_1|2021-03-04", '|'), date);
+        assert_eq!(header_date("s1 2021-03-04 England", '|'), date);
+        assert_eq!(header_date("hCoV-19/England/MILK-1/2021|EPI_ISL_1|2021-03", '|'), None);
+    }
+
+    #[test]
+    fn civil_days_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(18690), (2021, 3, 4));
+        assert_eq!(days_from_civil(2021, 3, 4), 18690);
+    }
+
+    #[test]
+    fn iso_week_labels() {
+        let label = |date| bin_label(bin_index(date, TimeBin::Week), TimeBin::Week);
+        assert_eq!(label((2021, 3, 4)), "2021-W09");
+        // the first days of January belong to the last week of the previous year
+        assert_eq!(label((2021, 1, 3)), "2020-W53");
+        assert_eq!(label((2021, 1, 4)), "2021-W01");
+        assert_eq!(label((2024, 12, 30)), "2025-W01");
+        assert_eq!(bin_dates(bin_index((2021, 3, 4), TimeBin::Week), TimeBin::Week),
+                   ((2021, 3, 1), (2021, 3, 7)));
+    }
+
+    #[test]
+    fn month_labels() {
+        let idx = bin_index((2020, 2, 11), TimeBin::Month);
+        assert_eq!(bin_label(idx, TimeBin::Month), "2020-02");
+        assert_eq!(bin_dates(idx, TimeBin::Month), ((2020, 2, 1), (2020, 2, 29)));
+        assert_eq!(bin_label(bin_index((2020, 12, 31), TimeBin::Month) + 1, TimeBin::Month), "2021-01");
+    }
+
+    #[test]
+    fn low_count_bins_are_not_reported() {
+        let dir = TestDir::new("time_bins");
+        let records = [("s1|2021-01-04", "ACGT"), ("s2|2021-01-20", "ACGA"), ("s3|2021-01-31", "ACCA"),
+                       ("s4|2021-03-02", "ACGT")];
+        let path = dir.alignment("a.fasta", &records);
+        let sequences: Vec<&str> = records.iter().map(|(_, x)| *x).collect();
+        let (map_vec, _) = counts(&sequences, &Mode::Standard);
+        let cli = entropy_args(&path, &["--time-bin", "month", "--min-bin-size", "2"]);
+        let mut file = check_fasta(&path, true).unwrap();
+        report_time_bins(&map_vec, &mut file, &cli).unwrap();
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_time_bins"),
+                   "Bin,Start,End,Sequences,Status\n\
+                    2021-01,2021-01-01,2021-01-31,3,OK\n\
+                    2021-02,2021-02-01,2021-02-28,0,Low_count\n\
+                    2021-03,2021-03-01,2021-03-31,1,Low_count\n");
+        let matrix = dir.read("a.fasta_shannon_entropy_output_time_entropy");
+        let rows: Vec<Vec<&str>> = matrix.lines().map(|x| x.split(',').collect()).collect();
+        assert_eq!(rows[0], ["Position", "2021-01", "2021-02", "2021-03"]);
+        assert_eq!(rows[1], ["1", "0", "NA", "NA"]);
+        assert_eq!(rows[3][2..], ["NA", "NA"]);
+        assert_eq!(rows[4][1].parse::<f64>().unwrap(), -(1.0f64/3.0*(1.0f64/3.0).log2() +
+                                                         2.0/3.0*(2.0f64/3.0).log2()));
+        assert_eq!(rows[4][2..], ["NA", "NA"]);
+    }
+}
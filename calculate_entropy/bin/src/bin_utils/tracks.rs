@@ -0,0 +1,141 @@
+/*
+    Genome browser tracks of the per position entropy in reference coordinates
+        Chromosome name: --chrom, else the reference header up to the first whitespace
+        Positions without a reference base or below the threshold are left out
+        bedGraph: 0-based half open interval and entropy of every position
+        WIG: variableStep, 1-based position and entropy of every position
+        BED: positions with entropy at or above the high entropy cutoff
+            name: entropy rounded to 3 decimals
+            score: entropy scaled to 0 - 1000 by the maximum entropy of the mode
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{considered_notations, create_output, output_name, valid_entropies};
+use super::get_args::EntropyArgs;
+use super::reference::Reference;
+
+pub fn report_tracks(map_vec: &[HashMap<char, f64>], genome_count: f64, file: &FileBufferHelper,
+                     cli: &EntropyArgs, reference: &Reference) -> Result<(), String> {
+    let chrom = chromosome_name(cli, reference);
+    let entropies = valid_entropies(map_vec, genome_count, &cli.counting);
+    // 1-based reference position and entropy
+    let reference_entropies: Vec<(usize, f64)> = reference.columns.iter().enumerate()
+        .filter_map(|(idx, column)| entropies[*column].map(|entropy| (idx + 1, entropy)))
+        .collect();
+    info!("{} of {} reference positions with a valid entropy on {}",
+          reference_entropies.len(), reference.columns.len(), chrom);
+    let stem = file.path.file_stem().map_or(String::from("alignment"),
+                                            |x| x.to_string_lossy().to_string());
+    if cli.tracks {
+        write_track(file, cli, "entropy.bedGraph", |writer| {
+            writeln!(writer, "track type=bedGraph name=\"{stem} entropy\" description=\"Shannon entropy\"")?;
+            reference_entropies.iter().try_for_each(|(position, entropy)|
+                writeln!(writer, "{chrom}\t{start}\t{position}\t{entropy}", start=position - 1))
+        })?;
+        write_track(file, cli, "entropy.wig", |writer| {
+            writeln!(writer, "track type=wiggle_0 name=\"{stem} entropy\" description=\"Shannon entropy\"")?;
+            writeln!(writer, "variableStep chrom={chrom} span=1")?;
+            reference_entropies.iter().try_for_each(|(position, entropy)|
+                writeln!(writer, "{position}\t{entropy}"))
+        })?;
+    }
+    if let Some(cutoff) = cli.high_entropy {
+        let maximum_entropy = (considered_notations(&cli.counting.mode).len() as f64).log2();
+        let high_entropy: Vec<&(usize, f64)> = reference_entropies.iter()
+                                                .filter(|(_, entropy)| *entropy >= cutoff)
+                                                .collect();
+        info!("{} positions with entropy of at least {}", high_entropy.len(), cutoff);
+        write_track(file, cli, "high_entropy.bed", |writer| {
+            writeln!(writer, "track name=\"{stem} high entropy\" description=\"Entropy >= {cutoff}\" useScore=1")?;
+            high_entropy.iter().try_for_each(|(position, entropy)|
+                writeln!(writer, "{chrom}\t{start}\t{position}\tH={entropy:.3}\t{score}",
+                         start=position - 1, score=(entropy/maximum_entropy*1000.0).round().min(1000.0)))
+        })?;
+    }
+    Ok(())
+}
+
+pub fn chromosome_name(cli: &EntropyArgs, reference: &Reference) -> String {
+    cli.chrom.clone().unwrap_or_else(|| reference.header
+                                        .split_whitespace()
+                                        .next()
+                                        .unwrap_or("chr")
+                                        .to_string())
+}
+
+fn write_track<F>(file: &FileBufferHelper, cli: &EntropyArgs, metric: &str,
+                  write_lines: F) -> Result<(), String>
+where F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()> {
+    let out_file_name = output_name(file, &cli.output, metric);
+    info!("Track output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut track_writer = BufWriter::new(out_file);
+    write_lines(&mut track_writer)
+        .and_then(|_| track_writer.flush())
+        .map_err(|e| format!("Unable to write {} - {}", out_file_name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check_fasta::check_fasta;
+    use crate::bin_utils::calculate_entropy::get_entropy;
+    use crate::bin_utils::get_args::Mode;
+    use crate::bin_utils::reference::load_reference;
+    use crate::bin_utils::testing::{counts, entropy_args, TestDir};
+
+    // the reference gap in column 3 and the gapped column 6 are left out
+    const SEQUENCES: [&str; 3] = ["AC-GTA", "AC-GA-", "AT-GA-"];
+
+    fn tracks(dir: &TestDir, options: &[&str]) {
+        let path = dir.alignment("a.fasta", &[("s1 sample", SEQUENCES[0]), ("s2", SEQUENCES[1]),
+                                              ("s3", SEQUENCES[2])]);
+        let mut file = check_fasta(&path, true).unwrap();
+        let reference = load_reference(&mut file, None).unwrap();
+        let (map_vec, genome_count) = counts(&SEQUENCES, &Mode::Standard);
+        report_tracks(&map_vec, genome_count, &file, &entropy_args(&path, options), &reference).unwrap();
+    }
+
+    #[test]
+    fn bedgraph_is_zero_based_half_open() {
+        let dir = TestDir::new("bedgraph");
+        tracks(&dir, &["--tracks"]);
+        // counts in A, T, G, C order
+        let (c_t, a_t) = (get_entropy(&[0.0, 1.0, 0.0, 2.0]), get_entropy(&[2.0, 1.0, 0.0, 0.0]));
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_entropy.bedGraph"),
+                   format!("track type=bedGraph name=\"a entropy\" description=\"Shannon entropy\"\n\
+                            s1\t0\t1\t0\n\
+                            s1\t1\t2\t{c_t}\n\
+                            s1\t2\t3\t0\n\
+                            s1\t3\t4\t{a_t}\n"));
+    }
+
+    #[test]
+    fn wig_is_one_based() {
+        let dir = TestDir::new("wig");
+        tracks(&dir, &["--tracks", "--chrom", "chr1"]);
+        let (c_t, a_t) = (get_entropy(&[0.0, 1.0, 0.0, 2.0]), get_entropy(&[2.0, 1.0, 0.0, 0.0]));
+        assert_eq!(dir.read("a.fasta_shannon_entropy_output_entropy.wig"),
+                   format!("track type=wiggle_0 name=\"a entropy\" description=\"Shannon entropy\"\n\
+                            variableStep chrom=chr1 span=1\n\
+                            1\t0\n\
+                            2\t{c_t}\n\
+                            3\t0\n\
+                            4\t{a_t}\n"));
+    }
+
+    #[test]
+    fn high_entropy_bed() {
+        let dir = TestDir::new("high_entropy");
+        tracks(&dir, &["--high-entropy", "0.5"]);
+        assert_eq!(dir.files(), ["a.fasta", "a.fasta_shannon_entropy_output_high_entropy.bed"]);
+        let bed = dir.read("a.fasta_shannon_entropy_output_high_entropy.bed");
+        // 0.918 of the maximum entropy of 2 bits in 'Standard' mode
+        assert_eq!(bed.lines().skip(1).collect::<Vec<&str>>(),
+                   ["s1\t1\t2\tH=0.918\t459", "s1\t3\t4\tH=0.918\t459"]);
+    }
+}
@@ -0,0 +1,175 @@
+/*
+    Sites only VCF of the variable columns against the reference
+        CHROM: --chrom, else the reference header up to the first whitespace
+        POS: reference position
+        Columns with a gap in the reference (insertions) are left out and counted
+        REF: reference base, ambiguity codes in the reference are written as N and flagged
+        ALT: other bases (A, C, G, T) observed in the column, most frequent first
+            multi-allelic columns give one record with all ALT alleles
+            columns without an ALT allele are not written
+        AN: sequences with A, C, G or T in the column
+            gaps and ambiguity codes are not called, they are not part of AN
+            in 'Standard' mode ambiguity codes are counted as "." and show up in GAPF
+        AC, AF: count and frequency of every ALT allele among AN
+        INFO
+            ENTROPY: Shannon entropy of the column, VALID when above the threshold
+            GAPF: fraction of sequences with "-" or "." in the column
+            AMBIG: sequences with an ambiguity code in the column ('All' mode)
+*/
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::alleles::ranked_alleles;
+use super::calculate_entropy::{considered_notations, create_output, get_entropy, notation_counts, output_name};
+use super::get_args::EntropyArgs;
+use super::reference::Reference;
+use super::tracks::chromosome_name;
+
+const CALLED_BASES: &str = "ACGT";
+const GAPS: &str = "-.";
+const AMBIGUITY_CODES: &str = "UWSMKRYBDHVN";
+
+pub fn report_vcf(map_vec: &[HashMap<char, f64>], genome_count: f64, file: &FileBufferHelper,
+                  cli: &EntropyArgs, reference: &Reference) -> Result<(), String> {
+    let chrom = chromosome_name(cli, reference);
+    let out_file_name = output_name(file, &cli.output, "variants.vcf");
+    info!("VCF output file: {}", out_file_name);
+    let out_file = create_output(&out_file_name, &cli.output)?;
+    let mut vcf_writer = BufWriter::new(out_file);
+    let header = [
+        String::from("##fileformat=VCFv4.2"),
+        format!("##source=calculate_entropy {}", env!("CARGO_PKG_VERSION")),
+        format!("##reference={}", reference.header),
+        format!("##contig=<ID={},length={}>", chrom, reference.columns.len()),
+        String::from("##INFO=<ID=AC,Number=A,Type=Integer,Description=\"Sequences carrying the ALT allele\">"),
+        String::from("##INFO=<ID=AN,Number=1,Type=Integer,Description=\"Sequences with A, C, G or T, gaps and ambiguity codes excluded\">"),
+        String::from("##INFO=<ID=AF,Number=A,Type=Float,Description=\"Frequency of the ALT allele among AN\">"),
+        String::from("##INFO=<ID=ENTROPY,Number=1,Type=Float,Description=\"Shannon entropy of the alignment column\">"),
+        format!("##INFO=<ID=VALID,Number=0,Type=Flag,Description=\"Fraction of considered notations at least {}\">", cli.counting.threshold),
+        String::from("##INFO=<ID=GAPF,Number=1,Type=Float,Description=\"Fraction of sequences with a gap\">"),
+        String::from("##INFO=<ID=AMBIG,Number=1,Type=Integer,Description=\"Sequences with an ambiguity code\">"),
+        String::from("##INFO=<ID=AMBIGUOUS_REF,Number=1,Type=String,Description=\"Ambiguity code of the reference, written as N in REF\">"),
+        String::from("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO"),
+    ];
+    header.iter().for_each(|x| writeln!(vcf_writer, "{}", x).expect("Unable to write to file"));
+
+    let atgc = considered_notations(&cli.counting.mode);
+    let mut records = 0usize;
+    let mut insertion_columns = 0usize;
+    let reference_bases = reference.sequence.as_bytes();
+    map_vec.iter().enumerate().for_each(|(idx, char_map)| {
+        let position = match reference.reference_position(idx) {
+            Some(x) => x,
+            None => {
+                insertion_columns += 1;
+                return
+            }
+        };
+        if let Some(record) = vcf_record(&chrom, position, reference_bases[idx] as char, char_map, atgc,
+                                         genome_count, cli.counting.threshold) {
+            records += 1;
+            writeln!(vcf_writer, "{record}").expect("Unable to write to file");
+        }
+    });
+    info!("{} variable sites written, {} columns with a gap in the reference left out",
+          records, insertion_columns);
+    Ok(())
+}
+
+// record of a column, None for columns without an ALT allele
+fn vcf_record(chrom: &str, position: usize, reference_base: char, char_map: &HashMap<char, f64>,
+              atgc: &str, genome_count: f64, threshold: f64) -> Option<String> {
+    let ref_allele = if CALLED_BASES.contains(reference_base) { reference_base } else { 'N' };
+    let alt_alleles: Vec<(char, f64)> = ranked_alleles(char_map).into_iter()
+                                            .filter(|(x, _)| *x != ref_allele)
+                                            .collect();
+    if alt_alleles.is_empty() {
+        return None
+    }
+    // counts are written as integers, as declared in the header
+    let allele_number: f64 = notation_counts(char_map, CALLED_BASES).iter().sum();
+    let gaps: f64 = notation_counts(char_map, GAPS).iter().sum();
+    let ambiguous: usize = AMBIGUITY_CODES.chars().filter_map(|x| char_map.get(&x)).map(|x| *x as usize).sum();
+    let atgc_count_vec = notation_counts(char_map, atgc);
+    let valid = atgc_count_vec.iter().sum::<f64>()/genome_count >= threshold;
+    let join = |f: &dyn Fn(&(char, f64)) -> String| alt_alleles.iter()
+                                                        .map(f)
+                                                        .collect::<Vec<String>>()
+                                                        .join(",");
+    let alt = join(&|(x, _)| x.to_string());
+    let allele_counts = join(&|(_, count)| (*count as usize).to_string());
+    let allele_frequencies = join(&|(_, count)| format!("{:.6}", count/allele_number));
+    let mut info = format!("AC={allele_counts};AN={allele_number};AF={allele_frequencies};\
+                            ENTROPY={entropy:.6};GAPF={gap_fraction:.6};AMBIG={ambiguous}",
+                           allele_number=allele_number as usize, entropy=get_entropy(&atgc_count_vec),
+                           gap_fraction=gaps/genome_count);
+    if valid {
+        info += ";VALID";
+    }
+    if ref_allele != reference_base {
+        info += &format!(";AMBIGUOUS_REF={reference_base}");
+    }
+    Some(format!("{chrom}\t{position}\t.\t{ref_allele}\t{alt}\t.\t.\t{info}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(counts: &[(char, f64)]) -> HashMap<char, f64> {
+        "ATGC-.".chars().map(|x| (x, 0.0)).chain(counts.iter().copied()).collect()
+    }
+
+    // INFO values of a record, checked against the types declared in the header
+    fn info_values(record: &str) -> HashMap<String, String> {
+        let fields: Vec<&str> = record.split('\t').collect();
+        assert_eq!(fields.len(), 8, "{record}");
+        assert!(fields[1].parse::<u64>().is_ok());
+        fields[7].split(';').map(|x| {
+            let (key, value) = x.split_once('=').unwrap_or((x, ""));
+            match key {
+                "AC" | "AN" | "AMBIG" => value.split(',')
+                                            .for_each(|x| assert!(x.parse::<u64>().is_ok(), "{key}={x}")),
+                "AF" | "ENTROPY" | "GAPF" => value.split(',')
+                                                .for_each(|x| assert!(x.parse::<f64>().is_ok(), "{key}={x}")),
+                "VALID" => assert!(value.is_empty()),
+                "AMBIGUOUS_REF" => assert_eq!(value.len(), 1),
+                _ => panic!("Undeclared INFO field {key}")
+            }
+            (key.to_string(), value.to_string())
+        }).collect()
+    }
+
+    #[test]
+    fn counts_are_integers_without_ambiguity_codes() {
+        let record = vcf_record("MN908947.3", 4, 'A', &column(&[('A', 5.0), ('G', 1.0)]), "ATGC", 6.0, 0.8)
+                        .unwrap();
+        assert!(record.starts_with("MN908947.3\t4\t.\tA\tG\t.\t.\t"));
+        let info = info_values(&record);
+        assert_eq!(info["AC"], "1");
+        assert_eq!(info["AN"], "6");
+        assert_eq!(info["AMBIG"], "0");
+        assert!(info.contains_key("VALID"));
+    }
+
+    #[test]
+    fn ambiguity_codes_and_gaps_are_counted() {
+        let char_map = column(&[('A', 3.0), ('T', 2.0), ('C', 1.0), ('-', 1.0), ('N', 2.0), ('R', 1.0)]);
+        let record = vcf_record("chr", 10, 'Y', &char_map, "ATGC", 10.0, 0.8).unwrap();
+        let info = info_values(&record);
+        assert!(record.starts_with("chr\t10\t.\tN\tA,T,C\t"));
+        assert_eq!(info["AC"], "3,2,1");
+        assert_eq!(info["AN"], "6");
+        assert_eq!(info["AMBIG"], "3");
+        assert_eq!(info["GAPF"], "0.100000");
+        assert_eq!(info["AMBIGUOUS_REF"], "Y");
+        assert!(!info.contains_key("VALID"));
+    }
+
+    #[test]
+    fn columns_without_alt_alleles_are_not_written() {
+        assert_eq!(vcf_record("chr", 1, 'A', &column(&[('A', 6.0)]), "ATGC", 6.0, 0.8), None);
+    }
+}
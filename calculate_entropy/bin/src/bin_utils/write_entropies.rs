@@ -1,9 +1,165 @@
 /* 
-    write out the final shannon entropy
+    write out the run summary next to the final shannon entropy
     request file name suffix name
-        output log file
-        corresponding statistics file
-        final position - entropy file
-        complete output file
+        output log file, with --log (or --popgen for the entropy subcommand)
+            JSON Lines, one record per non-permissible character found by update_counts
+            position (1 based), header and character, in the order they are found
+            written while counting, the warnings are not kept in memory
+        corresponding statistics file, with --statistics (or --popgen for the entropy subcommand)
+            sequences, alignment length, valid and invalid positions
+            mean and median entropy of the valid positions
+            count and fraction of every symbol in the alignment
+            number of warnings
+    The stats subcommand writes only the statistics, the log with --log and the provenance with --provenance
 */
 
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, JoinHandle};
+use log::info;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use super::calculate_entropy::{create_output, initialise_structs, main_output_name, metric_output_name, open_output,
+                               output_name, process_genomes, valid_entropies};
+use super::get_args::{CountingOptions, OutputOptions, StatsArgs};
+use super::output_format::json_string;
+use super::provenance::write_provenance;
+
+// warnings waiting for the log writer before the counting threads block
+const WARNING_BUFFER: usize = 1024;
+
+pub struct CountWarning {
+    // 1-based alignment position
+    pub position: usize,
+    pub header: String,
+    pub character: char,
+}
+
+// log file written by its own thread, fed with the warnings of the counting threads
+pub struct WarningLog {
+    sender: SyncSender<CountWarning>,
+    writer: JoinHandle<()>,
+}
+
+impl WarningLog {
+    pub fn create(file: &FileBufferHelper, output: &OutputOptions) -> Result<WarningLog, String> {
+        let out_file_name = output_name(file, output, "log.jsonl");
+        info!("Log output file: {}", out_file_name);
+        let out_file = create_output(&out_file_name, output)?;
+        let (sender, receiver) = sync_channel::<CountWarning>(WARNING_BUFFER);
+        let writer = thread::spawn(move || {
+            let mut log_writer = BufWriter::new(out_file);
+            receiver.iter().for_each(|x| {
+                writeln!(log_writer, "{{\"level\":\"WARN\",\"event\":\"non_permissible_character\",\
+                                      \"position\":{},\"header\":{},\"character\":{}}}",
+                         x.position, json_string(&x.header), json_string(&x.character.to_string()))
+                    .expect("Unable to write to file");
+            });
+        });
+        Ok(WarningLog { sender, writer })
+    }
+
+    pub fn sender(&self) -> &SyncSender<CountWarning> {
+        &self.sender
+    }
+
+    // close the channel and wait for the remaining warnings to be written
+    pub fn finish(self) {
+        drop(self.sender);
+        self.writer.join().expect("Unable to write the log");
+    }
+}
+
+// outputs of the stats subcommand for an alignment
+pub fn statistics_outputs(path: &Path, args: &StatsArgs) -> Vec<String> {
+    let mut outputs = vec![main_output_name(path, &args.output, "statistics")];
+    if args.log {
+        outputs.push(metric_output_name(path, &args.output, "log.jsonl"));
+    }
+    if args.output.provenance {
+        outputs.push(metric_output_name(path, &args.output, "provenance.json"));
+    }
+    outputs
+}
+
+// stats subcommand: count the alignment and write its statistics, and the log and the provenance when requested
+pub fn run_statistics(file: &mut FileBufferHelper, args: &StatsArgs) -> Result<(), String> {
+    let count_vec = initialise_structs(file, &args.counting.mode);
+    file.buffer_reset();
+    let log = match args.log {
+        true => Some(WarningLog::create(file, &args.output)?),
+        false => None
+    };
+    let (map_vec, genome_count, warnings) = process_genomes(count_vec, file, args.counting.nproc,
+                                                            log.as_ref().map(|x| x.sender()));
+    if let Some(log) = log {
+        log.finish();
+    }
+    let out_file_name = main_output_name(file.path, &args.output, "statistics");
+    write_statistics(&map_vec, genome_count, warnings, file, &args.counting, &args.output, &out_file_name)?;
+    match args.output.provenance {
+        true => write_provenance(map_vec.len(), genome_count, file, &args.output),
+        false => Ok(())
+    }
+}
+
+pub fn write_statistics(map_vec: &[HashMap<char, f64>], genome_count: f64, warnings: usize,
+                        file: &mut FileBufferHelper, counting: &CountingOptions, output: &OutputOptions,
+                        out_file_name: &str) -> Result<(), String> {
+    let mut entropies: Vec<f64> = valid_entropies(map_vec, genome_count, counting).into_iter()
+                                    .flatten()
+                                    .filter(|x| !x.is_nan())
+                                    .collect();
+    entropies.sort_by(|a, b| a.total_cmp(b));
+    let valid_positions = entropies.len();
+    let (mean, median) = if entropies.is_empty() {
+        (String::from("NA"), String::from("NA"))
+    } else {
+        let middle = valid_positions/2;
+        let median = if valid_positions.is_multiple_of(2) {
+            (entropies[middle - 1] + entropies[middle])/2.0
+        } else {
+            entropies[middle]
+        };
+        ((entropies.iter().sum::<f64>()/valid_positions as f64).to_string(), median.to_string())
+    };
+    let composition = alignment_composition(file);
+    let cells: u64 = composition.values().sum();
+    let mut symbols: Vec<(&char, &u64)> = composition.iter().collect();
+    symbols.sort();
+
+    info!("Statistics output file: {}", out_file_name);
+    let out_file = open_output(out_file_name, output)?;
+    let mut statistics_writer = BufWriter::new(out_file);
+    writeln!(statistics_writer, "Statistic{}Value", output.delimiter).expect("Unable to write to file");
+    let mut statistics: Vec<(String, String)> = vec![
+        (String::from("Sequences"), genome_count.to_string()),
+        (String::from("Alignment_length"), map_vec.len().to_string()),
+        (String::from("Valid_positions"), valid_positions.to_string()),
+        (String::from("Invalid_positions"), (map_vec.len() - valid_positions).to_string()),
+        (String::from("Mean_entropy"), mean),
+        (String::from("Median_entropy"), median),
+    ];
+    symbols.iter().for_each(|(symbol, count)| {
+        statistics.push((format!("Count_{symbol}"), count.to_string()));
+        statistics.push((format!("Fraction_{symbol}"), (**count as f64/cells as f64).to_string()));
+    });
+    statistics.push((String::from("Warnings"), warnings.to_string()));
+    statistics.iter().for_each(|(statistic, value)| {
+        writeln!(statistics_writer, "{statistic}{}{value}", output.delimiter).expect("Unable to write to file");
+    });
+    info!("{} valid positions, mean entropy {}, {} warnings", valid_positions, statistics[4].1, warnings);
+    Ok(())
+}
+
+// counts of every character (upper case) over all sequences of the alignment
+pub fn alignment_composition(file: &mut FileBufferHelper) -> HashMap<char, u64> {
+    let mut composition: HashMap<char, u64> = HashMap::new();
+    file.buffer_reset();
+    while let Some((_, genome)) = file.next_record() {
+        genome.chars().for_each(|x| *composition.entry(x.to_ascii_uppercase()).or_insert(0) += 1);
+    }
+    file.buffer_reset();
+    composition
+}
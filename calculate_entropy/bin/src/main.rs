@@ -17,32 +17,94 @@ Libs
     count: count the occurence of unique characters across a column
     report: calculate the shannon entropy of each column and export along with column position
 
-Arguments
-    get path to alignment file
-    flag to include other alphabets
+Subcommands
+    validate: check_fasta only
+    entropy: shannon entropy at every position and the optional reports
+    stats: summary statistics of the alignment
+    consensus: consensus sequence of the alignment
+    convert: alignment in another format
+    every subcommand takes the alignments with -i and skips the ones failing check_fasta
+    the outputs of all alignments are checked before the first alignment is read
+        outputs written more than once, or existing with --no-clobber, stop the run
+    the exit code is a failure when an alignment is skipped or an output cannot be written
+    options can be read from a --config file, overridden by the command line
 */
 mod bin_utils;
-use clap::Parser;
-use bin_utils::get_args::Cli;
+use std::path::Path;
+use std::process::ExitCode;
+use bin_utils::config::parse_args;
+use bin_utils::get_args::{Command, InputOptions};
 use check_fasta::check_fasta;
-use bin_utils::calculate_entropy::report_entropy;
+use check_fasta::lib_utils::struct_helper::FileBufferHelper;
+use bin_utils::calculate_entropy::{check_outputs, entropy_outputs, report_entropy};
+use bin_utils::consensus::{consensus_outputs, run_consensus};
+use bin_utils::convert::{conversion_outputs, run_conversion};
+use bin_utils::write_entropies::{run_statistics, statistics_outputs};
 use log::{debug, info};
-use env_logger;
-fn main() {
+fn main() -> ExitCode {
     // Path to alignment file 
     // Mode of operation
     // Characters to ignore
     env_logger::init();
-    let cli = Cli::parse();
+    let cli = parse_args();
     // debug!("Parsing commandline arguments");
-    for file in &cli.input_alignment {
+    let planned = match &cli.command {
+        Command::Validate(_) => Ok(()),
+        Command::Entropy(args) => check_outputs(&planned_outputs(&args.input, |x| entropy_outputs(x, args)),
+                                                &args.output),
+        Command::Stats(args) => check_outputs(&planned_outputs(&args.input, |x| statistics_outputs(x, args)),
+                                              &args.output),
+        Command::Consensus(args) => check_outputs(&planned_outputs(&args.input, |x| consensus_outputs(x, args)),
+                                                  &args.output),
+        Command::Convert(args) => check_outputs(&planned_outputs(&args.input, |x| conversion_outputs(x, args)),
+                                                &args.output)
+    };
+    if let Err(e) = planned {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE
+    }
+    let valid = match &cli.command {
+        Command::Validate(args) => for_each_alignment(&args.input, |file| {
+            println!("{:?}: valid", file.path);
+            Ok(())
+        }),
+        Command::Entropy(args) => for_each_alignment(&args.input, |file| report_entropy(file, args)),
+        Command::Stats(args) => for_each_alignment(&args.input, |file| run_statistics(file, args)),
+        Command::Consensus(args) => for_each_alignment(&args.input, |file| run_consensus(file, args)),
+        Command::Convert(args) => for_each_alignment(&args.input, |file| run_conversion(file, args))
+    };
+    if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// output files of all input alignments
+fn planned_outputs<F: Fn(&Path) -> Vec<String>>(input: &InputOptions, outputs: F) -> Vec<String> {
+    input.input_alignment.iter().flat_map(|x| outputs(x)).collect()
+}
+
+// validate every input alignment and run the subcommand on the valid ones
+// false when an alignment is invalid or its outputs could not be written
+fn for_each_alignment<F>(input: &InputOptions, mut run: F) -> bool
+where F: FnMut(&mut FileBufferHelper) -> Result<(), String> {
+    let mut valid = true;
+    for file in &input.input_alignment {
         debug!("Processing file: {:?}", file);
-        match check_fasta(&file, true) {
+        match check_fasta(file, true) {
             Ok(mut alignment_file) => {
                 info!("Alignment complies requirements {:?}", file);
-                report_entropy(&mut alignment_file, &cli);
+                if let Err(e) = run(&mut alignment_file) {
+                    eprintln!("{}", e);
+                    valid = false;
+                }
             },
-            Err(e) => eprintln!("{}", e)
+            Err(e) => {
+                eprintln!("{}", e);
+                valid = false;
+            }
         }
     }
-}
\ No newline at end of file
+    valid
+}
@@ -18,7 +18,7 @@ use lib_utils::struct_helper::FileBufferHelper;
 
 const IUPAC_DNA_ALIGNMENT: &str = "ATGCUWSMKRYBDHVN-.";
 const IUPAC_DNA_ALIGNMENT_LOWER: &str = "atgcuwsmkrybdhvn";
-pub fn check_fasta(infile: &PathBuf, length_check: bool) -> Result<FileBufferHelper, &'static str> {
+pub fn check_fasta(infile: &PathBuf, length_check: bool) -> Result<FileBufferHelper<'_>, &'static str> {
     // check if the first line is ">", except empty space
     // lines after empty lines start with ">"
     let mut alignment_file =  match File::open(infile) {
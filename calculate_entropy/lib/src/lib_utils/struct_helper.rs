@@ -3,7 +3,7 @@
 
 
 use std::fs::File;
-use std::io::{BufReader, Seek, SeekFrom};
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
 use std::path::PathBuf;
 use log::debug;
 
@@ -14,13 +14,13 @@ pub struct FileBufferHelper<'a> {
 }
 
 impl<'a> FileBufferHelper<'a> {
-    pub fn new(file: &'a PathBuf) -> FileBufferHelper {
+    pub fn new(file: &'a PathBuf) -> FileBufferHelper<'a> {
         // initialise instant of FileBufferHelper
         let line = String::new();
         debug!("FileHelper created for: {:?}", file);
         let file_open = File::open(file.clone()).unwrap();
         Self {
-            path: &file,
+            path: file,
             buffer_reader: BufReader::new(file_open),
             line,
         }
@@ -32,4 +32,32 @@ impl<'a> FileBufferHelper<'a> {
         self.buffer_reader.seek(SeekFrom::Start(0)).expect("Unable to reset buffer");
         self.line.clear();
     }
+
+    pub fn next_record(&mut self) -> Option<(String, String)> {
+        // read the next header and its sequence from the buffer
+        // header is returned without the leading ">"
+        // the header line of the following record is held back in self.line
+        let mut header = String::new();
+        let mut sequence = String::new();
+        loop {
+            if self.line.is_empty() &&
+                self.buffer_reader.read_line(&mut self.line).unwrap_or(0) == 0 {
+                break
+            }
+            if self.line.starts_with('>') {
+                if !header.is_empty() {
+                    break
+                }
+                header = self.line.trim()[1..].to_string();
+            } else if !header.is_empty() {
+                sequence += self.line.trim();
+            }
+            self.line.clear();
+        }
+        if header.is_empty() {
+            None
+        } else {
+            Some((header, sequence))
+        }
+    }
 }
\ No newline at end of file